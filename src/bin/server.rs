@@ -0,0 +1,33 @@
+use bevy_rapier_3d_car_sim::{
+    brain::StartBrains, cli::*, config::Config, library::*, net::*, noise::Noise,
+};
+
+// dedicated race server, clients join with --connect <host:port>
+fn main() {
+    let args = Args::parse();
+    if args.connect.is_some() || args.players > 0 || args.race {
+        exit_with("--connect, --players and --race are client flags, the server always races");
+    }
+    let library = Library::default();
+    let mut config = Config {
+        race_mode: true,
+        car_sensors: args.car_sensors,
+        ..Config::default()
+    };
+    if let Some(seed) = args.seed {
+        config.seed = seed;
+    }
+    let start_brains = match args.brains.is_empty() {
+        true => StartBrains::default(),
+        false => library_start_brains(&library, &args.brains, &config.sensor_layout())
+            .unwrap_or_else(|e| exit_with(&e)),
+    };
+    let noise = match &args.noise {
+        Some(path) => Noise::load(path).unwrap_or_else(|e| exit_with(&e)),
+        None => Noise::default(),
+    };
+    let port = args.port.unwrap_or(DEFAULT_PORT);
+    server_app(port, config, start_brains, noise)
+        .unwrap_or_else(|e| exit_with(&format!("unable to listen on port {}: {}", port, e)))
+        .run();
+}
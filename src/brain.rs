@@ -1,38 +1,81 @@
 use crate::car::*;
 use crate::config::Config;
+use crate::noise::*;
+use crate::rays::RayLines;
+use crate::rng::SimRng;
 use bevy::prelude::*;
-// use bevy_prototype_debug_lines::DebugLines;
-use bevy_rapier3d::prelude::*;
-use rand::prelude::*;
-use rand::{distributions::Standard, Rng};
+use bevy_rapier3d::{prelude::*, rapier::prelude::InteractionGroups};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-fn car_lerp(a: f32, random_0_to_1: f32) -> f32 {
+const CLOSING_SPEED_MAX: f32 = 50.;
+
+// distance and closing speed inputs of a ray hitting a car toi away,
+// a car pulling away closes at zero
+fn car_ray_inputs(
+    toi: f32,
+    max_toi: f32,
+    ray_dir: Vec3,
+    linvel: Vec3,
+    other_linvel: Option<Vec3>,
+) -> (f32, f32) {
+    let closing = other_linvel.map_or(0., |v| (linvel - v).dot(ray_dir));
+    (
+        1. - toi / max_toi,
+        (closing / CLOSING_SPEED_MAX).clamp(0., 1.),
+    )
+}
+
+fn car_lerp(a: f32, random_0_to_1: f32, t: f32) -> f32 {
     let b = random_0_to_1 * 2. - 1.;
-    let t = 0.01;
     a + (b - a) * t
 }
 
+#[derive(Default, Clone)]
+pub struct StartBrains {
+    pub brains: Vec<CarBrain>,
+    pub mutate: bool,
+    // population of every world by world index, restored from a checkpoint
+    pub worlds: Vec<Vec<CarBrain>>,
+}
+
+impl StartBrains {
+    // a world the checkpoint has no population of mutates the rendered world's
+    pub fn of_world(&self, world: usize) -> StartBrains {
+        match self.worlds.get(world) {
+            Some(brains) if !brains.is_empty() => StartBrains {
+                brains: brains.clone(),
+                mutate: false,
+                worlds: vec![],
+            },
+            _ => StartBrains {
+                brains: self.brains.clone(),
+                mutate: self.mutate || !self.worlds.is_empty(),
+                worlds: vec![],
+            },
+        }
+    }
+}
+
 #[derive(Debug, Component, Clone, Serialize, Deserialize)]
 pub struct CarBrain {
     pub levels: Vec<Level>,
 }
 impl CarBrain {
-    pub fn new(n_ins: usize) -> CarBrain {
-        let ins = Level::new(n_ins, n_ins + 1);
-        let hidden = Level::new(n_ins + 1, 4);
+    pub fn new<R: Rng>(n_ins: usize, rng: &mut R) -> CarBrain {
+        let ins = Level::new(n_ins, n_ins + 1, rng);
+        let hidden = Level::new(n_ins + 1, 4, rng);
         CarBrain {
             levels: [ins, hidden].to_vec(),
         }
     }
-    pub fn feed_forward(&mut self, new_inputs: Vec<f32>) {
-        let mut outputs: Vec<f32> = new_inputs.clone();
-        for level in self.levels.iter_mut() {
-            level.feed_forward(outputs.clone());
-            outputs = level.outputs.clone();
+    pub fn topology(&self) -> Vec<usize> {
+        let mut topology: Vec<usize> = self.levels.iter().map(|l| l.n_in).collect();
+        if let Some(last) = self.levels.last() {
+            topology.push(last.n_out());
         }
+        topology
     }
-
     // pub fn mutate_random(&mut self) {
     //     let mut rng = rand::thread_rng();
     //     for level in self.levels.iter_mut() {
@@ -47,66 +90,180 @@ impl CarBrain {
     //     }
     // }
 
-    pub fn clone_randomised(brain: &CarBrain) -> CarBrain {
-        let mut rng = rand::thread_rng();
+    pub fn clone_randomised<R: Rng>(brain: &CarBrain, mutation: f32, rng: &mut R) -> CarBrain {
         let mut levels: Vec<Level> = vec![];
         for level in brain.levels.iter() {
             let mut cloned_level = level.clone();
-            cloned_level.inputs.fill(0.);
-            cloned_level.outputs.fill(0.);
             for bias in cloned_level.biases.iter_mut() {
-                *bias = car_lerp(*bias, rng.gen::<f32>());
+                *bias = car_lerp(*bias, rng.gen::<f32>(), mutation);
             }
-            for weighti in cloned_level.weights.iter_mut() {
-                for weight in weighti.iter_mut() {
-                    *weight = car_lerp(*weight, rng.gen::<f32>());
-                }
+            for weight in cloned_level.weights.iter_mut() {
+                *weight = car_lerp(*weight, rng.gen::<f32>(), mutation);
             }
             levels.push(cloned_level)
         }
-        return CarBrain { levels };
+        CarBrain { levels }
+    }
+}
+
+// mean standard deviation of every weight and bias across the population
+pub fn weight_diversity(brains: &[&CarBrain]) -> f32 {
+    let params: Vec<Vec<f32>> = brains
+        .iter()
+        .map(|b| {
+            b.levels
+                .iter()
+                .flat_map(|l| l.weights.iter().chain(l.biases.iter()))
+                .cloned()
+                .collect()
+        })
+        .collect();
+    let n_params = params.iter().map(|p| p.len()).min().unwrap_or(0);
+    if params.len() < 2 || n_params == 0 {
+        return 0.;
+    }
+    let n = params.len() as f32;
+    let mut std_sum = 0.;
+    for i in 0..n_params {
+        let mean = params.iter().map(|p| p[i]).sum::<f32>() / n;
+        let variance = params.iter().map(|p| (p[i] - mean).powi(2)).sum::<f32>() / n;
+        std_sum += variance.sqrt();
     }
+    std_sum / n_params as f32
+}
+
+// sensor inputs of every car gathered first, then evaluated level by level
+#[derive(Default)]
+pub struct BrainBatch {
+    pub entities: Vec<Entity>,
+    pub inputs: Vec<f32>,
+    // a row per car for every level
+    pub layers: Vec<Vec<f32>>,
+    origins: Vec<Vec3>,
+    dirs: Vec<Vec3>,
+    hit_points: Vec<Vec3>,
 }
 
+// inputs and level outputs of the followed car from the last frame
+#[derive(Debug, Default)]
+pub struct BrainActivity {
+    pub car: Option<Entity>,
+    pub inputs: Vec<f32>,
+    pub layers: Vec<Vec<f32>>,
+}
+
+// weights are row-major [out][in] so each output is a contiguous dot product
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "LevelData", into = "LevelData")]
 pub struct Level {
-    pub inputs: Vec<f32>,
-    pub outputs: Vec<f32>,
+    pub n_in: usize,
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+// serialized level, weights nested [in][out] as in the original brain.json
+#[derive(Serialize, Deserialize)]
+pub struct LevelData {
     pub weights: Vec<Vec<f32>>,
     pub biases: Vec<f32>,
 }
 
-impl Level {
-    pub fn new(n_in: usize, n_out: usize) -> Level {
-        let inputs: Vec<f32> = vec![0.; n_in];
-        let outputs: Vec<f32> = vec![0.; n_out];
-        let weights: Vec<Vec<f32>> = (0..n_in)
-            .map(|_| thread_rng().sample_iter(Standard).take(n_out).collect())
+impl TryFrom<LevelData> for Level {
+    type Error = String;
+    fn try_from(data: LevelData) -> Result<Self, String> {
+        let n_in = data.weights.len();
+        let n_out = data.biases.len();
+        if let Some(row) = data.weights.iter().find(|row| row.len() != n_out) {
+            return Err(format!(
+                "a level input has {} weights for {} outputs",
+                row.len(),
+                n_out
+            ));
+        }
+        let mut weights: Vec<f32> = vec![0.; n_in * n_out];
+        for (i, row) in data.weights.iter().enumerate() {
+            for (o, w) in row.iter().enumerate() {
+                weights[o * n_in + i] = *w;
+            }
+        }
+        Ok(Level {
+            n_in,
+            weights,
+            biases: data.biases,
+        })
+    }
+}
+
+impl From<Level> for LevelData {
+    fn from(level: Level) -> Self {
+        let weights = (0..level.n_in)
+            .map(|i| (0..level.n_out()).map(|o| level.weight(i, o)).collect())
             .collect();
-        let biases: Vec<f32> = thread_rng().sample_iter(Standard).take(n_out).collect();
+        LevelData {
+            weights,
+            biases: level.biases,
+        }
+    }
+}
+
+impl Level {
+    pub fn new<R: Rng>(n_in: usize, n_out: usize, rng: &mut R) -> Level {
+        let mut weights: Vec<f32> = vec![0.; n_in * n_out];
+        for i in 0..n_in {
+            for o in 0..n_out {
+                weights[o * n_in + i] = rng.gen::<f32>();
+            }
+        }
+        let biases: Vec<f32> = (0..n_out).map(|_| rng.gen::<f32>()).collect();
 
         Level {
+            n_in,
             weights,
             biases,
-            inputs,
-            outputs,
         }
     }
-    pub fn feed_forward(&mut self, new_inputs: Vec<f32>) {
-        for (index, input) in self.inputs.iter_mut().enumerate() {
-            *input = new_inputs[index];
+    pub fn n_out(&self) -> usize {
+        self.biases.len()
+    }
+    pub fn weight(&self, index_in: usize, index_out: usize) -> f32 {
+        self.weights[index_out * self.n_in + index_in]
+    }
+    pub fn feed_forward(&self, inputs: &[f32], outputs: &mut [f32]) {
+        let rows = self.weights.chunks_exact(self.n_in.max(1));
+        for ((output, row), bias) in outputs.iter_mut().zip(rows).zip(self.biases.iter()) {
+            let sum: f32 = row.iter().zip(inputs.iter()).map(|(w, x)| w * x).sum();
+            *output = if sum > *bias { 1. } else { 0. };
         }
-        for (index_out, output) in self.outputs.iter_mut().enumerate() {
-            let mut sum: f32 = 0.;
-            for (index_in, input) in self.inputs.iter_mut().enumerate() {
-                sum = sum + *input * self.weights[index_in][index_out];
-            }
-            // println!("s {sum:.2} {:.2}", self.biases[index_out]);
-            if sum > self.biases[index_out] {
-                *output = 1.;
-            } else {
-                *output = 0.;
-            }
+    }
+}
+
+// one level over the whole batch before the next, each car with its own weights,
+// the batch shares one topology as every brain is checked against the sensors
+pub fn feed_forward_batch<'b>(
+    entities: &[Entity],
+    inputs: &[f32],
+    n_inputs: usize,
+    layers: &mut Vec<Vec<f32>>,
+    brain: impl Fn(Entity) -> &'b CarBrain,
+) {
+    let rows = entities.len();
+    let n_levels = entities.first().map_or(0, |&e| brain(e).levels.len());
+    layers.resize_with(n_levels, Vec::new);
+    for l in 0..n_levels {
+        let (done, rest) = layers.split_at_mut(l);
+        let (level_inputs, width) = match l {
+            0 => (inputs, n_inputs),
+            _ => (done[l - 1].as_slice(), done[l - 1].len() / rows),
+        };
+        let n_out = brain(entities[0]).levels[l].n_out();
+        let outputs = &mut rest[0];
+        outputs.resize(rows * n_out, 0.);
+        let rows_in = level_inputs.chunks_exact(width);
+        let rows_out = outputs.chunks_exact_mut(n_out);
+        for ((&e, row_in), row_out) in entities.iter().zip(rows_in).zip(rows_out) {
+            let level = &brain(e).levels[l];
+            debug_assert_eq!(level.n_out(), n_out);
+            level.feed_forward(row_in, row_out);
         }
     }
 }
@@ -114,23 +271,45 @@ impl Level {
 pub fn car_brain_system(
     rapier_context: Res<RapierContext>,
     config: Res<Config>,
-    mut q_car: Query<(Entity, &mut Car, &mut CarBrain, &Children), With<Car>>,
+    noise: Res<Noise>,
+    mut rng: ResMut<SimRng>,
+    mut batch: Local<BrainBatch>,
+    mut activity: ResMut<BrainActivity>,
+    mut q_car: Query<
+        (Entity, &mut Car, &CarBrain, &mut CarNoise, &Children),
+        (With<Car>, Without<RemoteCar>),
+    >,
     q_near: Query<(&GlobalTransform, With<SensorNear>)>,
     q_far: Query<(&GlobalTransform, With<SensorFar>)>,
-    mut ray_set: ParamSet<(
-        Query<(&mut Transform, With<RayOrig>)>,
-        Query<(&mut Transform, With<RayDir>)>,
-        Query<(&mut Transform, With<RayHit>)>,
-    )>,
-    // mut lines: ResMut<DebugLines>,
+    q_parent: Query<&Parent, With<Collider>>,
+    q_velocity: Query<&Velocity, With<Car>>,
+    // absent in the headless worlds
+    mut ray_lines: Option<ResMut<RayLines>>,
 ) {
     let sensor_filter = QueryFilter::new().exclude_dynamic().exclude_sensors();
+    let car_groups = InteractionGroups::new(CAR_SENSOR_GROUP, CAR_TRAINING_GROUP);
+    let n_inputs = config.brain_inputs();
+
+    let BrainBatch {
+        entities,
+        inputs: batch_inputs,
+        layers,
+        origins,
+        dirs,
+        hit_points,
+    } = &mut *batch;
+    entities.clear();
+    batch_inputs.clear();
+    if let Some(ref mut ray_lines) = ray_lines {
+        ray_lines.segments.clear();
+    }
 
     let e_hid_car = config.hid_car.unwrap();
-    for (e, mut car, mut brain, children) in q_car.iter_mut() {
-        let is_hid_car = e == e_hid_car;
-        let mut origins: Vec<Vec3> = Vec::new();
-        let mut dirs: Vec<Vec3> = Vec::new();
+    let e_followed = config.camera_follow.unwrap_or(e_hid_car);
+    for (e, car, _, mut car_noise, children) in q_car.iter_mut() {
+        let draw_rays = config.show_rays && (config.rays_all_cars || e == e_followed);
+        origins.clear();
+        dirs.clear();
 
         for &child in children.iter() {
             if let Ok((gtrf, _)) = q_near.get(child) {
@@ -141,19 +320,14 @@ pub fn car_brain_system(
             }
         }
 
-        let mut inputs: Vec<f32> = vec![0.; config.sensor_count];
-        let mut hit_points: Vec<Vec3> = vec![Vec3::ZERO; config.sensor_count];
+        let row_start = batch_inputs.len();
+        batch_inputs.resize(row_start + n_inputs, 0.);
+        let inputs = &mut batch_inputs[row_start..];
+        hit_points.clear();
+        hit_points.resize(config.sensor_count, Vec3::ZERO);
         let solid = false;
         for (i, &ray_dir_pos) in dirs.iter().enumerate() {
             let ray_pos = origins[i];
-            // if is_hid_car {
-            //     lines.line_colored(
-            //         ray_pos,
-            //         ray_dir_pos,
-            //         0.0,
-            //         Color::rgba(0.25, 0.88, 0.82, 0.1),
-            //     );
-            // }
             let ray_dir = (ray_dir_pos - ray_pos).normalize();
             rapier_context.intersections_with_ray(
                 ray_pos,
@@ -166,14 +340,6 @@ pub fn car_brain_system(
                     hit_points[i] = intersection.point;
                     if toi > 0. {
                         inputs[i] = 1. - toi / config.max_toi;
-                        // if config.show_rays {
-                        //     lines.line_colored(
-                        //         ray_pos,
-                        //         intersection.point,
-                        //         0.0,
-                        //         Color::rgba(0.98, 0.5, 0.45, 0.9),
-                        //     );
-                        // }
                     } else {
                         inputs[i] = 0.;
                     }
@@ -181,32 +347,128 @@ pub fn car_brain_system(
                 },
             );
         }
-        if is_hid_car {
-            for (i, (mut trf, _)) in ray_set.p0().iter_mut().enumerate() {
-                trf.translation = origins[i];
-            }
-            for (i, (mut trf, _)) in ray_set.p1().iter_mut().enumerate() {
-                trf.translation = dirs[i];
+        if config.car_sensors {
+            let n = config.sensor_count;
+            let car_filter = QueryFilter::new()
+                .exclude_sensors()
+                .exclude_rigid_body(e)
+                .groups(car_groups);
+            let linvel = q_velocity.get(e).map_or(Vec3::ZERO, |v| v.linvel);
+            for (i, &ray_dir_pos) in dirs.iter().enumerate() {
+                let ray_pos = origins[i];
+                let ray_dir = (ray_dir_pos - ray_pos).normalize();
+                let hit =
+                    rapier_context.cast_ray(ray_pos, ray_dir, config.max_toi, solid, car_filter);
+                if let Some((e_hit, toi)) = hit {
+                    let other = q_parent.get(e_hit).and_then(|p| q_velocity.get(p.get()));
+                    let other = other.ok().map(|v| v.linvel);
+                    let (car_input, closing) =
+                        car_ray_inputs(toi, config.max_toi, ray_dir, linvel, other);
+                    // a car behind a wall is not visible
+                    if car_input < inputs[i] {
+                        continue;
+                    }
+                    inputs[n + i] = car_input;
+                    inputs[2 * n + i] = closing;
+                }
             }
-            for (i, (mut trf, _)) in ray_set.p2().iter_mut().enumerate() {
-                trf.translation = hit_points[i];
+        }
+        if let (true, Some(ray_lines)) = (draw_rays, ray_lines.as_mut()) {
+            for (i, &ray_dir_pos) in dirs.iter().enumerate() {
+                let hit = hit_points[i] != Vec3::ZERO;
+                let end = match hit {
+                    true => hit_points[i],
+                    false => origins[i] + (ray_dir_pos - origins[i]).normalize() * config.max_toi,
+                };
+                ray_lines.push_ray(origins[i], end, hit, config.max_toi);
             }
-            // print_float_arr("inputs", &inputs);
         }
         if !car.use_brain {
-            return;
+            batch_inputs.truncate(row_start);
+            continue;
         }
-        brain.feed_forward(inputs.clone());
+        car_noise.sensors(&noise, &mut rng, config.sensor_count, inputs);
+        entities.push(e);
+    }
 
-        let outputs: &Vec<f32> = &brain.levels.last().unwrap().outputs;
+    feed_forward_batch(entities, batch_inputs, n_inputs, layers, |e| {
+        q_car.get(e).unwrap().2
+    });
+
+    let rows = entities.len();
+    let last = match layers.last() {
+        Some(last) => last,
+        None => return,
+    };
+    let n_out = last.len() / rows.max(1);
+    for (k, (&e, outputs)) in entities.iter().zip(last.chunks_exact(n_out)).enumerate() {
+        let (_, mut car, _, mut car_noise, _) = q_car.get_mut(e).unwrap();
         // print_float_arr("outputs", outputs);
 
         let gas = outputs[0];
         let brake = outputs[1];
         let left = outputs[2];
         let right = outputs[3];
+        if e == e_followed {
+            activity.car = Some(e);
+            activity.inputs.clear();
+            activity
+                .inputs
+                .extend_from_slice(&batch_inputs[k * n_inputs..(k + 1) * n_inputs]);
+            activity.layers.resize_with(layers.len(), Vec::new);
+            for (car_layer, layer) in activity.layers.iter_mut().zip(layers.iter()) {
+                let width = layer.len() / rows;
+                car_layer.clear();
+                car_layer.extend_from_slice(&layer[k * width..(k + 1) * width]);
+            }
+        }
+        let (gas, brake, steering) =
+            car_noise.actuators(&noise, &mut rng, gas, brake, -left + right);
         car.gas = gas;
         car.brake = brake;
-        car.steering = -left + right;
+        car.steering = steering;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_matches_one_car_at_a_time() {
+        let mut rng = SimRng::new(7);
+        let n_inputs = 5;
+        let brains: Vec<CarBrain> = (0..3)
+            .map(|_| CarBrain::new(n_inputs, &mut rng.rng))
+            .collect();
+        let entities: Vec<Entity> = (0..3).map(Entity::from_raw).collect();
+        let inputs: Vec<f32> = (0..3 * n_inputs).map(|i| (i % 4) as f32 / 3.).collect();
+        let mut layers = vec![];
+        feed_forward_batch(&entities, &inputs, n_inputs, &mut layers, |e| {
+            &brains[e.id() as usize]
+        });
+
+        for (k, brain) in brains.iter().enumerate() {
+            let mut hidden = vec![0.; n_inputs + 1];
+            let mut outputs = vec![0.; 4];
+            let car_inputs = &inputs[k * n_inputs..(k + 1) * n_inputs];
+            brain.levels[0].feed_forward(car_inputs, &mut hidden);
+            brain.levels[1].feed_forward(&hidden, &mut outputs);
+            assert_eq!(outputs, &layers[1][k * 4..(k + 1) * 4]);
+        }
+    }
+
+    #[test]
+    fn a_car_ahead_reads_distance_and_closing_speed() {
+        // 10 m ahead at 5 m/s while the ray's car does 20 m/s
+        let (distance, closing) =
+            car_ray_inputs(10., 50., Vec3::Z, Vec3::Z * 20., Some(Vec3::Z * 5.));
+        assert!((distance - 0.8).abs() < 1e-6);
+        assert!((closing - 15. / CLOSING_SPEED_MAX).abs() < 1e-6);
+        // pulling away, still seen but not closing
+        let (distance, closing) =
+            car_ray_inputs(10., 50., Vec3::Z, Vec3::Z * 5., Some(Vec3::Z * 20.));
+        assert!(distance > 0.);
+        assert_eq!(closing, 0.);
     }
 }
@@ -0,0 +1,449 @@
+use crate::brain::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const BRAIN_FORMAT_VERSION: u32 = 1;
+const BINARY_MAGIC: &[u8; 4] = b"CBRN";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrainFormat {
+    Json,
+    Binary,
+}
+
+impl BrainFormat {
+    // brain.bin is written in binary, anything else in json
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("bin") => BrainFormat::Binary,
+            _ => BrainFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorLayout {
+    pub sensor_count: usize,
+    pub car_sensors: bool,
+    pub max_toi: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    Step,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrainHeader {
+    pub format_version: u32,
+    pub topology: Vec<usize>,
+    pub activation: Activation,
+    pub sensors: SensorLayout,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrainFile {
+    pub header: BrainHeader,
+    pub brain: CarBrain,
+}
+
+#[derive(Debug)]
+pub enum BrainError {
+    NotFound(PathBuf),
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Corrupt(PathBuf, String),
+    Version(PathBuf, u32),
+    Topology(PathBuf, String),
+    Sensors(PathBuf, SensorLayout, SensorLayout),
+}
+
+impl fmt::Display for BrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BrainError::NotFound(path) => write!(f, "{} not found", path.display()),
+            BrainError::Io(path, e) => write!(f, "unable to access {}: {}", path.display(), e),
+            BrainError::Parse(path, e) => {
+                write!(f, "{} is not a valid brain file: {}", path.display(), e)
+            }
+            BrainError::Corrupt(path, msg) => {
+                write!(f, "{} is a corrupt binary brain: {}", path.display(), msg)
+            }
+            BrainError::Version(path, version) => write!(
+                f,
+                "{} has format version {}, this build supports up to {}",
+                path.display(),
+                version,
+                BRAIN_FORMAT_VERSION
+            ),
+            BrainError::Topology(path, msg) => {
+                write!(f, "{} has inconsistent topology: {}", path.display(), msg)
+            }
+            BrainError::Sensors(path, found, expected) => write!(
+                f,
+                "{} was trained with sensors {:?}, current sensors are {:?}",
+                path.display(),
+                found,
+                expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BrainError {}
+
+impl BrainFile {
+    pub fn new(brain: &CarBrain, sensors: SensorLayout) -> Self {
+        Self {
+            header: BrainHeader {
+                format_version: BRAIN_FORMAT_VERSION,
+                topology: brain.topology(),
+                activation: Activation::Step,
+                sensors,
+            },
+            brain: brain.clone(),
+        }
+    }
+
+    pub fn validate(&self, path: &Path) -> Result<(), BrainError> {
+        let topology_err = |msg: String| BrainError::Topology(path.to_path_buf(), msg);
+        let topology = &self.header.topology;
+        if topology.len() != self.brain.levels.len() + 1 {
+            return Err(topology_err(format!(
+                "header {:?} for {} levels",
+                topology,
+                self.brain.levels.len()
+            )));
+        }
+        for (i, level) in self.brain.levels.iter().enumerate() {
+            let (n_in, n_out) = (topology[i], topology[i + 1]);
+            if level.n_in != n_in
+                || level.weights.len() != n_in * n_out
+                || level.biases.len() != n_out
+            {
+                return Err(topology_err(format!(
+                    "level {} weights don't match {}x{}",
+                    i, n_in, n_out
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // a brain only drives a car with the sensors it was trained with
+    pub fn check_sensors(&self, path: &Path, sensors: &SensorLayout) -> Result<(), BrainError> {
+        if &self.header.sensors != sensors || self.header.topology[0] != sensors.inputs() {
+            return Err(BrainError::Sensors(
+                path.to_path_buf(),
+                self.header.sensors.clone(),
+                sensors.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// reads either format, unversioned json is migrated assuming the given sensors
+pub fn read_brain_file(path: &Path, sensors: &SensorLayout) -> Result<BrainFile, BrainError> {
+    let bytes = fs::read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => BrainError::NotFound(path.to_path_buf()),
+        _ => BrainError::Io(path.to_path_buf(), e),
+    })?;
+    let file = match bytes.starts_with(BINARY_MAGIC) {
+        true => {
+            decode_binary(&bytes).map_err(|msg| BrainError::Corrupt(path.to_path_buf(), msg))?
+        }
+        false => {
+            let parse_err = |e| BrainError::Parse(path.to_path_buf(), e);
+            let value: serde_json::Value = serde_json::from_slice(&bytes).map_err(parse_err)?;
+            match value.get("header") {
+                Some(_) => serde_json::from_value(value).map_err(parse_err)?,
+                None => {
+                    // brain.json before the header was introduced
+                    println!("{} has no header, migrating", path.display());
+                    let brain: CarBrain = serde_json::from_value(value).map_err(parse_err)?;
+                    BrainFile::new(&brain, sensors.clone())
+                }
+            }
+        }
+    };
+    if file.header.format_version > BRAIN_FORMAT_VERSION {
+        return Err(BrainError::Version(
+            path.to_path_buf(),
+            file.header.format_version,
+        ));
+    }
+    file.validate(path)?;
+    Ok(file)
+}
+
+pub fn load_brain(path: &Path, sensors: &SensorLayout) -> Result<CarBrain, BrainError> {
+    let file = read_brain_file(path, sensors)?;
+    file.check_sensors(path, sensors)?;
+    Ok(file.brain)
+}
+
+pub fn save_brain(path: &Path, brain: &CarBrain, sensors: SensorLayout) -> Result<(), BrainError> {
+    write_brain_file(path, &BrainFile::new(brain, sensors))
+}
+
+pub fn write_brain_file(path: &Path, file: &BrainFile) -> Result<(), BrainError> {
+    let serialized = match BrainFormat::from_path(path) {
+        BrainFormat::Binary => encode_binary(file),
+        BrainFormat::Json => {
+            serde_json::to_vec(file).map_err(|e| BrainError::Parse(path.to_path_buf(), e))?
+        }
+    };
+    fs::write(path, serialized).map_err(|e| BrainError::Io(path.to_path_buf(), e))
+}
+
+// rewrites a brain file in the format given by the target extension
+pub fn convert_brain(from: &Path, to: &Path, sensors: &SensorLayout) -> Result<(), BrainError> {
+    let file = read_brain_file(from, sensors)?;
+    write_brain_file(to, &file)?;
+    println!(
+        "converted {} to {} ({:?})",
+        from.display(),
+        to.display(),
+        BrainFormat::from_path(to)
+    );
+    Ok(())
+}
+
+// FNV-1a
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+// magic, version, activation, sensors, topology, then per level the [in][out]
+// weights and the biases as little endian f32, followed by a checksum
+pub fn encode_binary(file: &BrainFile) -> Vec<u8> {
+    let header = &file.header;
+    let mut bytes: Vec<u8> = BINARY_MAGIC.to_vec();
+    bytes.extend(header.format_version.to_le_bytes());
+    bytes.push(match header.activation {
+        Activation::Step => 0,
+    });
+    bytes.extend((header.sensors.sensor_count as u32).to_le_bytes());
+    bytes.push(header.sensors.car_sensors as u8);
+    bytes.extend(header.sensors.max_toi.to_le_bytes());
+    bytes.extend((header.topology.len() as u32).to_le_bytes());
+    for n in header.topology.iter() {
+        bytes.extend((*n as u32).to_le_bytes());
+    }
+    for level in file.brain.levels.iter() {
+        let data = LevelData::from(level.clone());
+        for w in data.weights.iter().flatten().chain(data.biases.iter()) {
+            bytes.extend(w.to_le_bytes());
+        }
+    }
+    bytes.extend(checksum(&bytes).to_le_bytes());
+    bytes
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let chunk = self
+            .bytes
+            .get(self.at..self.at + N)
+            .ok_or_else(|| format!("truncated at byte {}", self.at))?;
+        self.at += N;
+        Ok(chunk.try_into().unwrap())
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+}
+
+pub fn decode_binary(bytes: &[u8]) -> Result<BrainFile, String> {
+    if bytes.len() < BINARY_MAGIC.len() + 4 {
+        return Err("too short".to_string());
+    }
+    let (payload, sum) = bytes.split_at(bytes.len() - 4);
+    if checksum(payload) != u32::from_le_bytes(sum.try_into().unwrap()) {
+        return Err("checksum mismatch".to_string());
+    }
+    let mut r = ByteReader {
+        bytes: payload,
+        at: BINARY_MAGIC.len(),
+    };
+    let format_version = r.u32()?;
+    let activation = match r.u8()? {
+        0 => Activation::Step,
+        a => return Err(format!("unknown activation {}", a)),
+    };
+    let sensors = SensorLayout {
+        sensor_count: r.u32()? as usize,
+        car_sensors: r.u8()? != 0,
+        max_toi: r.f32()?,
+    };
+    let n_topology = r.u32()? as usize;
+    if n_topology < 2 || n_topology > (payload.len() - r.at) / 4 {
+        return Err(format!("invalid topology length {}", n_topology));
+    }
+    let topology = (0..n_topology)
+        .map(|_| r.u32().map(|n| n as usize))
+        .collect::<Result<Vec<usize>, String>>()?;
+    let mut levels: Vec<Level> = vec![];
+    for pair in topology.windows(2) {
+        let (n_in, n_out) = (pair[0], pair[1]);
+        let weights = (0..n_in)
+            .map(|_| (0..n_out).map(|_| r.f32()).collect())
+            .collect::<Result<Vec<Vec<f32>>, String>>()?;
+        let biases = (0..n_out)
+            .map(|_| r.f32())
+            .collect::<Result<Vec<f32>, String>>()?;
+        levels.push(Level::try_from(LevelData { weights, biases })?);
+    }
+    if r.at != payload.len() {
+        return Err(format!("{} trailing bytes", payload.len() - r.at));
+    }
+    Ok(BrainFile {
+        header: BrainHeader {
+            format_version,
+            topology,
+            activation,
+            sensors,
+        },
+        brain: CarBrain { levels },
+    })
+}
+
+impl SensorLayout {
+    pub fn inputs(&self) -> usize {
+        match self.car_sensors {
+            true => self.sensor_count * 3,
+            false => self.sensor_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SimRng;
+
+    fn sensors() -> SensorLayout {
+        SensorLayout {
+            sensor_count: 5,
+            car_sensors: false,
+            max_toi: 50.,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("car-sim-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn unversioned_json_is_migrated_with_the_given_sensors() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(3).rng);
+        let path = temp_path("legacy.json");
+        fs::write(&path, serde_json::to_vec(&brain).unwrap()).unwrap();
+
+        let file = read_brain_file(&path, &sensors()).unwrap();
+        assert_eq!(file.header.format_version, BRAIN_FORMAT_VERSION);
+        assert_eq!(file.header.topology, brain.topology());
+        assert_eq!(file.header.sensors, sensors());
+        let loaded = load_brain(&path, &sensors()).unwrap();
+        assert_eq!(loaded.levels[0].weights, brain.levels[0].weights);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_other_sensors_newer_versions_and_missing_files() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(3).rng);
+        let path = temp_path("saved.json");
+        save_brain(&path, &brain, sensors()).unwrap();
+        let car_sensors = SensorLayout {
+            car_sensors: true,
+            ..sensors()
+        };
+        assert!(matches!(
+            load_brain(&path, &car_sensors),
+            Err(BrainError::Sensors(..))
+        ));
+
+        let mut file = BrainFile::new(&brain, sensors());
+        file.header.format_version = BRAIN_FORMAT_VERSION + 1;
+        write_brain_file(&path, &file).unwrap();
+        assert!(matches!(
+            load_brain(&path, &sensors()),
+            Err(BrainError::Version(_, v)) if v == BRAIN_FORMAT_VERSION + 1
+        ));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            load_brain(&path, &sensors()),
+            Err(BrainError::NotFound(_))
+        ));
+    }
+    #[test]
+    fn binary_round_trips() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(5).rng);
+        let file = BrainFile::new(&brain, sensors());
+        let decoded = decode_binary(&encode_binary(&file)).unwrap();
+        assert_eq!(decoded.header.topology, file.header.topology);
+        assert_eq!(decoded.header.sensors, file.header.sensors);
+        for (a, b) in decoded.brain.levels.iter().zip(brain.levels.iter()) {
+            assert_eq!(a.weights, b.weights);
+            assert_eq!(a.biases, b.biases);
+        }
+    }
+
+    #[test]
+    fn load_rejects_a_ragged_brain_file() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(3).rng);
+        let mut value = serde_json::to_value(&brain).unwrap();
+        value["levels"][0]["weights"][1]
+            .as_array_mut()
+            .unwrap()
+            .pop();
+        let path = temp_path("ragged.json");
+        fs::write(&path, serde_json::to_vec(&value).unwrap()).unwrap();
+        assert!(matches!(
+            load_brain(&path, &sensors()),
+            Err(BrainError::Parse(..))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn binary_rejects_corruption() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(5).rng);
+        let mut bytes = encode_binary(&BrainFile::new(&brain, sensors()));
+        let mid = bytes.len() / 2;
+        bytes[mid] ^= 0xff;
+        assert_eq!(decode_binary(&bytes).unwrap_err(), "checksum mismatch");
+        assert!(decode_binary(&bytes[..6]).is_err());
+        assert!(decode_binary(BINARY_MAGIC).is_err());
+    }
+
+    #[test]
+    fn convert_keeps_the_brain_across_formats() {
+        let brain = CarBrain::new(sensors().inputs(), &mut SimRng::new(5).rng);
+        let (json, bin) = (temp_path("convert.json"), temp_path("convert.bin"));
+        save_brain(&json, &brain, sensors()).unwrap();
+        convert_brain(&json, &bin, &sensors()).unwrap();
+        assert!(fs::read(&bin).unwrap().starts_with(BINARY_MAGIC));
+        let loaded = load_brain(&bin, &sensors()).unwrap();
+        assert_eq!(loaded.levels[1].weights, brain.levels[1].weights);
+        fs::remove_file(&json).unwrap();
+        fs::remove_file(&bin).unwrap();
+    }
+}
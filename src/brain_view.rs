@@ -0,0 +1,263 @@
+use crate::{brain::*, chart::place_segment, config::Config, trainer::Trainer};
+use bevy::prelude::*;
+
+const PANEL: Vec2 = Vec2::new(360., 240.);
+const LABELS_IN: f32 = 80.;
+const LABELS_OUT: f32 = 50.;
+const NODE: f32 = 8.;
+pub const OUTPUT_NAMES: [&str; 4] = ["gas", "brake", "left", "right"];
+
+#[derive(Component, Default)]
+pub struct BrainView {
+    pub topology: Vec<usize>,
+    // car and generation the edges were drawn for
+    pub drawn: Option<(Entity, i32)>,
+}
+
+#[derive(Component)]
+pub struct BrainViewPart;
+
+#[derive(Component)]
+pub struct BrainViewNode {
+    pub column: usize,
+    pub index: usize,
+}
+
+#[derive(Component)]
+pub struct BrainViewEdge {
+    pub level: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+// matches the sensor order of car_start_system and car_brain_system
+pub fn sensor_names(config: &Config) -> Vec<String> {
+    let half = config.sensor_count as i32 / 2;
+    let angles: Vec<String> = (-half..(half + 1))
+        .map(|a| format!("{:+.0}°", a as f32 * 0.02 * 180.))
+        .collect();
+    let mut names: Vec<String> = angles.iter().map(|a| format!("ray {}", a)).collect();
+    if config.car_sensors {
+        names.extend(angles.iter().map(|a| format!("car {}", a)));
+        names.extend(angles.iter().map(|a| format!("closing {}", a)));
+    }
+    names
+}
+
+fn node_position(topology: &[usize], column: usize, index: usize) -> Vec2 {
+    let columns = (topology.len().max(2) - 1) as f32;
+    let x = LABELS_IN + column as f32 * (PANEL.x - LABELS_IN - LABELS_OUT) / columns;
+    let step = PANEL.y / (topology[column] + 1) as f32;
+    Vec2::new(x, PANEL.y - step * (index + 1) as f32)
+}
+
+fn value_color(v: f32) -> Color {
+    let v = v.clamp(0., 1.);
+    Color::rgb(0.2 + 0.8 * v, 0.2 + 0.64 * v, 0.2 - 0.2 * v)
+}
+
+fn weight_color(w: f32, max: f32) -> (Color, f32) {
+    let m = (w.abs() / max.max(f32::EPSILON)).min(1.);
+    let alpha = 0.15 + 0.6 * m;
+    let color = match w >= 0. {
+        true => Color::rgba(0.3, 0.9, 0.4, alpha),
+        false => Color::rgba(0.95, 0.3, 0.3, alpha),
+    };
+    (color, 1. + 2. * m)
+}
+
+pub fn brain_view_start_system(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(40.0),
+                    right: Val::Px(15.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(PANEL.x), Val::Px(PANEL.y)),
+                ..default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
+            ..default()
+        })
+        .insert(Name::new("Brain view"))
+        .insert(BrainView::default());
+}
+
+fn spawn_brain_view(
+    commands: &mut Commands,
+    view_e: Entity,
+    font: Handle<Font>,
+    topology: &[usize],
+    input_names: &[String],
+) {
+    let label_style = TextStyle {
+        font,
+        font_size: 10.0,
+        color: Color::WHITE,
+    };
+    commands.entity(view_e).with_children(|parent| {
+        for level in 0..topology.len() - 1 {
+            for from in 0..topology[level] {
+                for to in 0..topology[level + 1] {
+                    let mut style = Style {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    };
+                    let mut transform = Transform::default();
+                    let a = node_position(topology, level, from);
+                    let b = node_position(topology, level + 1, to);
+                    place_segment(&mut style, &mut transform, a, b, 1.);
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style,
+                            transform,
+                            color: UiColor(Color::NONE),
+                            ..default()
+                        })
+                        .insert(BrainViewPart)
+                        .insert(BrainViewEdge { level, from, to });
+                }
+            }
+        }
+        let last = topology.len() - 1;
+        for (column, &n) in topology.iter().enumerate() {
+            for index in 0..n {
+                let p = node_position(topology, column, index);
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                left: Val::Px(p.x - NODE / 2.),
+                                bottom: Val::Px(p.y - NODE / 2.),
+                                ..default()
+                            },
+                            size: Size::new(Val::Px(NODE), Val::Px(NODE)),
+                            ..default()
+                        },
+                        color: UiColor(value_color(0.)),
+                        ..default()
+                    })
+                    .insert(BrainViewPart)
+                    .insert(BrainViewNode { column, index });
+                let name = match column {
+                    0 => input_names.get(index).cloned(),
+                    c if c == last => OUTPUT_NAMES.get(index).map(|n| n.to_string()),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    let left = match column {
+                        0 => 4.,
+                        _ => p.x + NODE,
+                    };
+                    parent
+                        .spawn_bundle(TextBundle {
+                            style: Style {
+                                position_type: PositionType::Absolute,
+                                position: UiRect {
+                                    left: Val::Px(left),
+                                    bottom: Val::Px(p.y - 6.),
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            text: Text::from_section(name, label_style.clone()),
+                            ..default()
+                        })
+                        .insert(BrainViewPart);
+                }
+            }
+        }
+    });
+}
+
+pub fn brain_view_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keys: Res<Input<KeyCode>>,
+    config: Res<Config>,
+    trainer: Res<Trainer>,
+    activity: Res<BrainActivity>,
+    q_brain: Query<&CarBrain>,
+    mut q_view: Query<(Entity, &mut BrainView, &mut Style), Without<BrainViewEdge>>,
+    q_parts: Query<Entity, With<BrainViewPart>>,
+    mut q_nodes: Query<(&BrainViewNode, &mut UiColor), Without<BrainViewEdge>>,
+    mut q_edges: Query<
+        (&BrainViewEdge, &mut Style, &mut Transform, &mut UiColor),
+        Without<BrainView>,
+    >,
+) {
+    let (view_e, mut view, mut style) = q_view.single_mut();
+    if keys.just_pressed(KeyCode::B) {
+        style.display = match style.display {
+            Display::None => Display::Flex,
+            Display::Flex => Display::None,
+        };
+    }
+    if style.display == Display::None {
+        return;
+    }
+    let e = match config.camera_follow.or(config.hid_car) {
+        Some(e) => e,
+        None => return,
+    };
+    let brain = match q_brain.get(e) {
+        Ok(brain) => brain,
+        Err(_) => return,
+    };
+
+    let topology = brain.topology();
+    if view.topology != topology {
+        for part in q_parts.iter() {
+            commands.entity(part).despawn_recursive();
+        }
+        let font: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+        spawn_brain_view(
+            &mut commands,
+            view_e,
+            font,
+            &topology,
+            &sensor_names(&config),
+        );
+        view.topology = topology;
+        view.drawn = None;
+        return;
+    }
+
+    if view.drawn != Some((e, trainer.generation)) {
+        let max = brain
+            .levels
+            .iter()
+            .flat_map(|l| l.weights.iter())
+            .fold(0., |m: f32, w| m.max(w.abs()));
+        for (edge, mut style, mut transform, mut color) in q_edges.iter_mut() {
+            let level = &brain.levels[edge.level];
+            let (edge_color, width) = weight_color(level.weight(edge.from, edge.to), max);
+            let a = node_position(&view.topology, edge.level, edge.from);
+            let b = node_position(&view.topology, edge.level + 1, edge.to);
+            place_segment(&mut style, &mut transform, a, b, width);
+            *color = UiColor(edge_color);
+        }
+        view.drawn = Some((e, trainer.generation));
+    }
+
+    if activity.car != Some(e) {
+        return;
+    }
+    for (node, mut color) in q_nodes.iter_mut() {
+        let values = match node.column {
+            0 => &activity.inputs,
+            c => match activity.layers.get(c - 1) {
+                Some(layer) => layer,
+                None => continue,
+            },
+        };
+        if let Some(v) = values.get(node.index) {
+            *color = UiColor(value_color(*v));
+        }
+    }
+}
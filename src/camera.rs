@@ -1,28 +1,132 @@
-use crate::car::HID;
-use crate::config::Config;
+use crate::car::{Car, CarId, HID};
+use crate::config::{Config, FollowMode};
+use crate::fitness::CarFitness;
+use crate::input::Player;
+use crate::players::PlayerCamera;
+use crate::progress::CarProgress;
+use crate::race::*;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
+use bevy::render::camera::Projection;
+use bevy_rapier3d::prelude::Velocity;
 use core::f32::consts::PI;
+use std::cmp::Ordering;
 
 pub fn camera_start_system(mut commands: Commands, config: Res<Config>) {
-    commands
-        .spawn_bundle(Camera3dBundle {
-            transform: Transform::from_translation(
-                config.translation + Vec3::Y * 15. + config.quat.mul_vec3(-Vec3::Z * 30.),
-            )
-            .looking_at(Vec3::Y * 6., config.translation),
+    let mut camera = commands.spawn_bundle(Camera3dBundle {
+        transform: Transform::from_translation(
+            config.translation + Vec3::Y * 15. + config.quat.mul_vec3(-Vec3::Z * 30.),
+        )
+        .looking_at(Vec3::Y * 6., config.translation),
+        ..default()
+    });
+    camera
+        .insert(CameraController {
+            // WSAD drives the second player's car in split screen
+            enabled: config.players < 2,
             ..default()
         })
-        .insert(CameraController::default());
+        .insert(PlayerCamera { player: 0 });
+    if config.players > 1 {
+        camera.insert(UiCameraConfig { show_ui: false });
+    }
     println!(
         "Controls:
         WSAD   - forward/back/strafe left/right
         LShift - run
         E      - up
-        Q      - down"
+        Q      - down
+        1      - follow own car
+        0      - free camera
+        [ ]    - follow previous/next car
+        2      - follow the leader
+        3      - follow the best brain
+        C      - chase/cockpit/orbit/tv camera"
     );
 }
 
+const BASE_FOV: f32 = PI / 4.;
+const CHASE_OFFSET: Vec3 = Vec3::new(0., 5., -25.);
+const CHASE_STIFFNESS: f32 = 20.;
+// critically damped for the stiffness above
+const CHASE_DAMPING: f32 = 9.;
+const CHASE_FOV_SPEED: f32 = 50.;
+const COCKPIT_OFFSET: Vec3 = Vec3::new(0., 0.9, 0.8);
+const TV_SPACING: f32 = 150.;
+const TV_HEIGHT: f32 = 6.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    Chase,
+    Cockpit,
+    Orbit,
+    Tv,
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::Chase => CameraMode::Cockpit,
+            CameraMode::Cockpit => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Tv,
+            CameraMode::Tv => CameraMode::Chase,
+        }
+    }
+}
+
+// trackside camera spots, meters along the polyline and position
+#[derive(Default)]
+pub struct TvCameras {
+    pub track_start: Option<Vec3>,
+    pub spots: Vec<(f32, Vec3)>,
+}
+
+impl TvCameras {
+    fn update(&mut self, config: &Config) {
+        let polyline = match &config.polyline {
+            Some(polyline) => polyline,
+            None => return,
+        };
+        let start = polyline.segment(0).a;
+        let start = Vec3::new(start.x, start.y, start.z);
+        if self.track_start == Some(start) {
+            return;
+        }
+        self.track_start = Some(start);
+        self.spots.clear();
+        let mut next = 0.;
+        for (i, segment) in polyline.segments().enumerate() {
+            let (a, b) = (segment.a, segment.b);
+            let (a, b) = (Vec3::new(a.x, a.y, a.z), Vec3::new(b.x, b.y, b.z));
+            let length = segment.length();
+            while next < config.meters[i] + length {
+                let t = (next - config.meters[i]) / length.max(f32::EPSILON);
+                let dir = (b - a).normalize_or_zero();
+                let side = Vec3::new(dir.z, 0., -dir.x) * (config.road_half_width + 12.);
+                self.spots
+                    .push((next, a.lerp(b, t) + side + Vec3::Y * TV_HEIGHT));
+                next += TV_SPACING;
+            }
+        }
+    }
+
+    // closest spot along the track, not in a straight line
+    fn nearest(&self, meters: f32, meters_total: f32) -> Option<Vec3> {
+        let distance = |spot: f32| {
+            let d = (spot - meters).abs();
+            d.min(meters_total - d)
+        };
+        self.spots
+            .iter()
+            .min_by(|a, b| {
+                distance(a.0)
+                    .partial_cmp(&distance(b.0))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(_, p)| *p)
+    }
+}
+
 #[derive(Component)]
 pub struct CameraController {
     pub enabled: bool,
@@ -40,6 +144,11 @@ pub struct CameraController {
     pub pitch: f32,
     pub yaw: f32,
     pub velocity: Vec3,
+    pub mode: CameraMode,
+    pub chase_velocity: Vec3,
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    pub orbit_distance: f32,
 }
 
 impl Default for CameraController {
@@ -60,51 +169,106 @@ impl Default for CameraController {
             pitch: 0.0,
             yaw: 0.0,
             velocity: Vec3::ZERO,
+            mode: CameraMode::Chase,
+            chase_velocity: Vec3::ZERO,
+            orbit_yaw: 0.,
+            orbit_pitch: 0.4,
+            orbit_distance: 20.,
         }
     }
 }
 
 pub fn camera_switch_system(
     mut config: ResMut<Config>,
+    race: Res<Race>,
     input: Res<Input<KeyCode>>,
     query: Query<Entity, With<HID>>,
+    q_cars: Query<(Entity, &CarId, &CarProgress, &CarFitness), With<Car>>,
 ) {
     if input.just_pressed(KeyCode::Key1) {
+        config.follow_mode = FollowMode::Car;
         config.camera_follow = Some(query.single());
     }
     if input.just_pressed(KeyCode::Key0) {
+        config.follow_mode = FollowMode::Car;
         config.camera_follow = None;
     }
+    if input.just_pressed(KeyCode::Key2) {
+        config.follow_mode = FollowMode::Leader;
+    }
+    if input.just_pressed(KeyCode::Key3) {
+        config.follow_mode = FollowMode::BestBrain;
+    }
+    let step: isize = match (
+        input.just_pressed(KeyCode::RBracket),
+        input.just_pressed(KeyCode::LBracket),
+    ) {
+        (true, false) => 1,
+        (false, true) => -1,
+        _ => 0,
+    };
+    if step != 0 {
+        let mut cars: Vec<(CarId, Entity)> = q_cars.iter().map(|(e, id, ..)| (*id, e)).collect();
+        cars.sort_by_key(|(id, _)| *id);
+        if !cars.is_empty() {
+            let n = cars.len() as isize;
+            let current = config
+                .camera_follow
+                .and_then(|f| cars.iter().position(|(_, e)| *e == f))
+                .map_or(if step > 0 { 0 } else { -1 }, |i| i as isize + step);
+            config.follow_mode = FollowMode::Car;
+            config.camera_follow = Some(cars[current.rem_euclid(n) as usize].1);
+        }
+    }
+    let target = match config.follow_mode {
+        FollowMode::Car => return,
+        FollowMode::Leader => race_standings(
+            &config,
+            &race,
+            q_cars
+                .iter()
+                .map(|(e, _, progress, _)| (e, progress.meters)),
+        )
+        .first()
+        .map(|s| s.entity),
+        FollowMode::BestBrain => q_cars
+            .iter()
+            .max_by(|a, b| a.3.total.partial_cmp(&b.3.total).unwrap_or(Ordering::Equal))
+            .map(|(e, ..)| e),
+    };
+    if target.is_some() && target != config.camera_follow {
+        config.camera_follow = target;
+    }
+}
+
+fn set_fov(projection: &mut Projection, fov: f32) {
+    if let Projection::Perspective(ref mut perspective) = projection {
+        if (perspective.fov - fov).abs() > f32::EPSILON {
+            perspective.fov = fov;
+        }
+    }
 }
 
 pub fn camera_controller_system(
     time: Res<Time>,
     config: Res<Config>,
+    mut tv_cameras: Local<TvCameras>,
     mut mouse_events: EventReader<MouseMotion>,
     key_input: Res<Input<KeyCode>>,
+    q_players: Query<(Entity, &Player)>,
     mut transforms: ParamSet<(
-        Query<(&mut Transform, &mut CameraController), With<Camera>>,
-        Query<&Transform, With<HID>>,
+        Query<
+            (
+                &mut Transform,
+                &mut CameraController,
+                &mut Projection,
+                Option<&PlayerCamera>,
+            ),
+            With<Camera>,
+        >,
+        Query<(&Transform, &Velocity, &CarProgress), (With<Car>, Without<Camera>)>,
     )>,
 ) {
-    if let Some(e) = config.camera_follow {
-        let p1 = transforms.p1();
-        let car_transform = p1.get(e);
-        if let Ok(car_transform) = car_transform {
-            let mut tf = Transform::from_matrix(car_transform.compute_matrix());
-            let shift_vec: Vec3 = tf.rotation.mul_vec3(Vec3::new(0., 5., -25.));
-            tf.translation.x = tf.translation.x + shift_vec.x;
-            tf.translation.y = tf.translation.y + shift_vec.y;
-            tf.translation.z = tf.translation.z + shift_vec.z;
-            tf.rotate(Quat::from_rotation_y(-PI));
-            tf.look_at(car_transform.translation + Vec3::new(0., 2., 0.), Vec3::Y);
-            for (mut cam_transform, _) in transforms.p0().iter_mut() {
-                *cam_transform = tf;
-            }
-        }
-
-        return;
-    }
     let dt = time.delta_seconds();
 
     let mut mouse_delta = Vec2::ZERO;
@@ -112,10 +276,109 @@ pub fn camera_controller_system(
         mouse_delta += mouse_event.delta;
     }
 
-    for (mut transform, mut options) in transforms.p0().iter_mut() {
+    // in split screen every camera follows its own player, the first one can still switch
+    let player_car = |index: usize| {
+        q_players
+            .iter()
+            .find(|(_, player)| player.index == index)
+            .map(|(e, _)| e)
+    };
+    let follows: Vec<Option<Entity>> = transforms
+        .p0()
+        .iter()
+        .map(
+            |(.., player_camera)| match player_camera.map_or(0, |p| p.player) {
+                0 if config.players < 2 => config.camera_follow,
+                0 => config.camera_follow.or_else(|| player_car(0)),
+                player => player_car(player),
+            },
+        )
+        .collect();
+    let targets: Vec<Option<(Transform, Vec3, f32)>> = follows
+        .iter()
+        .map(|follow| {
+            follow.and_then(|e| {
+                transforms
+                    .p1()
+                    .get(e)
+                    .ok()
+                    .map(|(t, v, progress)| (*t, v.linvel, progress.meters))
+            })
+        })
+        .collect();
+
+    for ((mut transform, mut options, mut projection, _), car) in
+        transforms.p0().iter_mut().zip(targets)
+    {
+        if let Some((car_t, linvel, meters)) = car {
+            let target = car_t.translation + Vec3::Y * 2.;
+            if key_input.just_pressed(KeyCode::C) {
+                options.mode = options.mode.next();
+                options.chase_velocity = Vec3::ZERO;
+                println!("camera {:?}", options.mode);
+            }
+            match options.mode {
+                CameraMode::Chase => {
+                    let desired = car_t.translation + car_t.rotation.mul_vec3(CHASE_OFFSET);
+                    let spring = CHASE_STIFFNESS * (desired - transform.translation)
+                        - CHASE_DAMPING * options.chase_velocity;
+                    options.chase_velocity += spring * dt;
+                    transform.translation += options.chase_velocity * dt;
+                    // car was reset or the target changed
+                    if transform.translation.distance(desired) > 100. {
+                        transform.translation = desired;
+                        options.chase_velocity = Vec3::ZERO;
+                    }
+                    transform.look_at(target, Vec3::Y);
+                    let speed = (linvel.length() / CHASE_FOV_SPEED).min(1.);
+                    set_fov(&mut projection, BASE_FOV + 0.35 * speed);
+                }
+                CameraMode::Cockpit => {
+                    transform.translation =
+                        car_t.translation + car_t.rotation.mul_vec3(COCKPIT_OFFSET);
+                    let ahead =
+                        car_t.translation + car_t.rotation.mul_vec3(Vec3::new(0., 0.7, 20.));
+                    transform.look_at(ahead, car_t.rotation.mul_vec3(Vec3::Y));
+                    set_fov(&mut projection, BASE_FOV * 1.3);
+                }
+                CameraMode::Orbit => {
+                    options.orbit_yaw -= mouse_delta.x * options.sensitivity * dt;
+                    options.orbit_pitch = (options.orbit_pitch
+                        + mouse_delta.y * 0.5 * options.sensitivity * dt)
+                        .clamp(0.05, 1.4);
+                    let (yaw, pitch) = (options.orbit_yaw, options.orbit_pitch);
+                    let dir = Vec3::new(
+                        yaw.sin() * pitch.cos(),
+                        pitch.sin(),
+                        yaw.cos() * pitch.cos(),
+                    );
+                    transform.translation = target + dir * options.orbit_distance;
+                    transform.look_at(target, Vec3::Y);
+                    set_fov(&mut projection, BASE_FOV);
+                }
+                CameraMode::Tv => {
+                    tv_cameras.update(&config);
+                    let along =
+                        (meters + config.meters_shift).rem_euclid(config.meters_total.max(1.));
+                    if let Some(spot) = tv_cameras.nearest(along, config.meters_total) {
+                        transform.translation = spot;
+                        transform.look_at(target, Vec3::Y);
+                        // zoom to keep the car about the same size on screen
+                        let distance = spot.distance(target).max(1.);
+                        set_fov(
+                            &mut projection,
+                            (2. * (8. / distance).atan()).clamp(0.1, BASE_FOV),
+                        );
+                    }
+                }
+            }
+            continue;
+        }
+
         if !options.enabled {
             continue;
         }
+        set_fov(&mut projection, BASE_FOV);
 
         let mut axis_input = Vec3::ZERO;
         if key_input.pressed(options.key_forward) {
@@ -171,3 +434,67 @@ pub fn camera_controller_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_rapier3d::na::Point3;
+    use parry3d::shape::Polyline;
+
+    #[test]
+    fn camera_modes_cycle_back_to_chase() {
+        let mut mode = CameraMode::Chase;
+        let mut seen = vec![];
+        for _ in 0..4 {
+            mode = mode.next();
+            seen.push(mode);
+        }
+        assert_eq!(
+            seen,
+            [
+                CameraMode::Cockpit,
+                CameraMode::Orbit,
+                CameraMode::Tv,
+                CameraMode::Chase
+            ]
+        );
+    }
+
+    #[test]
+    fn tv_spots_follow_the_polyline() {
+        let vertices = vec![
+            Point3::new(0., 0., 0.),
+            Point3::new(300., 0., 0.),
+            Point3::new(300., 0., 300.),
+        ];
+        let config = Config {
+            polyline: Some(Polyline::new(vertices, None)),
+            meters: vec![0., 300.],
+            meters_total: 600.,
+            ..default()
+        };
+        let mut tv = TvCameras::default();
+        tv.update(&config);
+        tv.update(&config);
+        let meters: Vec<f32> = tv.spots.iter().map(|s| s.0).collect();
+        assert_eq!(meters, [0., 150., 300., 450.]);
+        // halfway along the second segment, off the road to its right
+        assert_eq!(tv.spots[3].1, Vec3::new(320., TV_HEIGHT, 150.));
+    }
+
+    #[test]
+    fn nearest_goes_along_the_track_across_the_line() {
+        let tv = TvCameras {
+            track_start: None,
+            spots: [0., 300., 600., 900.]
+                .iter()
+                .map(|&m| (m, Vec3::X * m))
+                .collect(),
+        };
+        assert_eq!(tv.nearest(320., 1000.), Some(Vec3::X * 300.));
+        // 20 m before the line is closest to the spot at the line
+        assert_eq!(tv.nearest(980., 1000.), Some(Vec3::ZERO));
+        assert_eq!(tv.nearest(940., 1000.), Some(Vec3::X * 900.));
+        assert_eq!(TvCameras::default().nearest(10., 1000.), None);
+    }
+}
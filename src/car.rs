@@ -1,12 +1,29 @@
-use crate::{brain::*, config::Config, mesh::*, progress::CarProgress, track::*};
+use crate::{
+    brain::*,
+    brain_file::*,
+    config::Config,
+    contact::CarContacts,
+    fitness::{CarFitness, CarStats},
+    input::Player,
+    mesh::*,
+    noise::CarNoise,
+    progress::CarProgress,
+    race::grid_offset,
+    rng::SimRng,
+    termination::CarTermination,
+    track::*,
+    trainer::Trainer,
+};
 use bevy::prelude::*;
 use bevy_rapier3d::{parry::shape::Cylinder, prelude::*, rapier::prelude::JointAxesMask};
-use std::{f32::consts::PI, fs::File, path::Path};
+use std::{f32::consts::PI, path::Path};
 
 #[derive(Component)]
 pub struct Wheel {
     pub radius: f32,
     pub width: f32,
+    // contact patch slip speed from the last esp_system pass
+    pub slip: f32,
 }
 #[derive(Component)]
 pub struct WheelFront;
@@ -20,14 +37,6 @@ pub struct WheelFrontRight;
 pub struct SensorFar;
 #[derive(Component)]
 pub struct SensorNear;
-#[derive(Component)]
-pub struct RayDir;
-#[derive(Component)]
-pub struct RayOrig;
-#[derive(Component)]
-pub struct RayHit;
-#[derive(Component)]
-pub struct RayLine;
 #[derive(Component, Debug)]
 pub struct Car {
     pub gas: f32,
@@ -41,10 +50,17 @@ pub struct Car {
 }
 #[derive(Component)]
 pub struct HID;
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CarId(pub usize);
+#[derive(Component)]
+pub struct CarBody;
+// a car a race server drives, the client only shows the states it sends
+#[derive(Component)]
+pub struct RemoteCar;
 
 impl Car {
     pub fn new(
-        wheels: &Vec<Entity>,
+        wheels: &[Entity],
         use_brain: bool,
         wheel_max_torque: f32,
         init_transform: Transform,
@@ -54,7 +70,7 @@ impl Car {
             brake: 0.,
             steering: 0.,
             use_brain,
-            wheels: wheels.clone(),
+            wheels: wheels.to_vec(),
             wheel_max_torque,
             init_transform,
             reset_pause_until: 0.,
@@ -63,46 +79,54 @@ impl Car {
 }
 
 pub const CAR_TRAINING_GROUP: u32 = 0b001;
+pub const CAR_SENSOR_GROUP: u32 = 0b100;
+
+pub fn car_body_groups(car_collisions: bool) -> CollisionGroups {
+    match car_collisions {
+        true => CollisionGroups::new(
+            CAR_TRAINING_GROUP,
+            STATIC_GROUP | CAR_SENSOR_GROUP | CAR_TRAINING_GROUP,
+        ),
+        false => CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP | CAR_SENSOR_GROUP),
+    }
+}
+
+pub fn car_start_transform(config: &Config, i: usize) -> Transform {
+    let grid = match config.race_mode {
+        true => grid_offset(i),
+        false => -Vec3::Z * 5. * i as f32,
+    };
+    Transform::from_translation(
+        // config.translation,
+        config.translation + config.quat.mul_vec3(grid),
+    )
+    .with_rotation(config.quat)
+}
+
 pub fn car_start_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut config: ResMut<Config>,
+    mut rng: ResMut<SimRng>,
+    trainer: Res<Trainer>,
+    start_brains: Res<StartBrains>,
     asset_server: Res<AssetServer>,
 ) {
     let car_gl = asset_server.load("car-race.glb#Scene0");
 
-    let ray_point_half = 0.05;
-    let ray_point_size = ray_point_half * 2.;
-    let ray_point_mesh = Mesh::from(shape::Cube {
-        size: ray_point_size,
-    });
-    for _i in 0..config.sensor_count {
-        commands.spawn().insert(RayDir).insert_bundle(PbrBundle {
-            mesh: meshes.add(ray_point_mesh.clone()),
-            material: materials.add(Color::rgba(0.3, 0.9, 0.9, 0.5).into()),
-            ..default()
-        });
-        commands.spawn().insert(RayOrig).insert_bundle(PbrBundle {
-            mesh: meshes.add(ray_point_mesh.clone()),
-            material: materials.add(Color::rgba(0.3, 0.9, 0.9, 0.5).into()),
-            ..default()
-        });
-        commands.spawn().insert(RayHit).insert_bundle(PbrBundle {
-            mesh: meshes.add(ray_point_mesh.clone()),
-            material: materials.add(Color::rgba(0.9, 0.9, 0.9, 0.9).into()),
-            ..default()
-        });
-    }
-
-    let saved_brain: Option<CarBrain>;
-    let json_file = File::open(Path::new("brain.json"));
-    if json_file.is_ok() {
-        println!("brain.json found");
-        saved_brain = serde_json::from_reader(json_file.unwrap()).unwrap();
-    } else {
-        saved_brain = None;
-    }
+    let saved_brain: Option<CarBrain> =
+        match load_brain(Path::new("brain.json"), &config.sensor_layout()) {
+            Ok(brain) => {
+                println!("brain.json found");
+                Some(brain)
+            }
+            Err(BrainError::NotFound(_)) => None,
+            Err(e) => {
+                println!("{}, starting from scratch", e);
+                None
+            }
+        };
 
     let wheel_r: f32 = 0.4;
     let wheel_hw: f32 = 0.2;
@@ -123,17 +147,15 @@ pub fn car_start_system(
         Vec3::new(-shift.x, shift.y, -shift.z),
     ];
 
+    let car_collisions = config.race_mode || config.car_collisions;
+    config.car_collisions = car_collisions;
     for i in 0..config.cars_count {
         let is_hid = i == 0;
-        let car_transform = Transform::from_translation(
-            // config.translation,
-            config.translation + config.quat.mul_vec3(-Vec3::Z * 5. * i as f32),
-        )
-        .with_rotation(config.quat);
+        let car_transform = car_start_transform(&config, i);
 
         let mut wheels: Vec<Entity> = vec![];
         let mut joints: Vec<GenericJoint> = vec![];
-        for i in 0..4 {
+        for (i, anchor) in car_anchors.iter().enumerate() {
             let joint_mask = JointAxesMask::X
                 | JointAxesMask::Y
                 | JointAxesMask::Z
@@ -143,12 +165,12 @@ pub fn car_start_system(
             let joint = GenericJointBuilder::new(joint_mask)
                 .local_axis1(Vec3::X)
                 .local_axis2(Vec3::Y)
-                .local_anchor1(car_anchors[i])
+                .local_anchor1(*anchor)
                 .local_anchor2(Vec3::ZERO)
                 .build();
             joints.push(joint);
 
-            let wheel_transform = config.translation + config.quat.mul_vec3(car_anchors[i]);
+            let wheel_transform = car_transform.translation + config.quat.mul_vec3(*anchor);
             let wheel_cylinder = Cylinder::new(wheel_hw, wheel_r);
             let mesh = bevy_mesh(wheel_cylinder.to_trimesh(200));
             // let wheel_shape = SharedShape(Arc::new(wheel_cylinder));
@@ -173,6 +195,7 @@ pub fn car_start_system(
             let wheel = Wheel {
                 radius: wheel_r,
                 width: wheel_hw * 2.,
+                slip: 0.,
             };
             let wheel_id = commands
                 .spawn()
@@ -203,26 +226,32 @@ pub fn car_start_system(
                     .entity(wheel_id)
                     .insert(WheelFrontLeft)
                     .insert(WheelFront);
-            } else if i == 2 {
-                commands.entity(wheel_id).insert(WheelBack);
-            } else if i == 3 {
+            } else {
                 commands.entity(wheel_id).insert(WheelBack);
             }
         }
 
+        // players race the brains, a lone player in training only steers along with one
+        let human = i < config.players && (config.players > 1 || config.race_mode);
         let scale = 1.7;
         let scale_vec = Vec3::new(scale, scale, scale);
         let car = commands
             .spawn()
             .insert(Sleeping::disabled())
-            .insert(Name::new("Car"))
+            .insert(Name::new(format!("Car {}", i)))
+            .insert(CarId(i))
             .insert(Car::new(
                 &wheels,
-                config.use_brain,
+                config.use_brain && !human,
                 config.max_torque,
                 car_transform,
             ))
-            .insert(CarProgress { meters: 0. })
+            .insert(CarProgress::default())
+            .insert(CarStats::default())
+            .insert(CarFitness::default())
+            .insert(CarTermination::default())
+            .insert(CarNoise::new(config.brain_inputs()))
+            .insert(CarContacts::default())
             .insert(RigidBody::Dynamic)
             .insert(Velocity::zero())
             // .insert(ExternalImpulse::default())
@@ -251,7 +280,9 @@ pub fn car_start_system(
                     .insert(Collider::cuboid(car_hw, car_hh, car_hl))
                     .insert(Friction::coefficient(0.5))
                     .insert(Restitution::coefficient(0.))
-                    .insert(CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP))
+                    .insert(car_body_groups(car_collisions))
+                    .insert(ActiveEvents::COLLISION_EVENTS)
+                    .insert(CarBody)
                     .insert(collider_mass);
 
                 let half = config.sensor_count as i8 / 2;
@@ -279,19 +310,45 @@ pub fn car_start_system(
             config.hid_car = Some(car);
             commands.entity(car).insert(HID);
         }
+        if i < config.players {
+            commands.entity(car).insert(Player::new(i));
+        }
         for (i, wheel_id) in wheels.iter().enumerate() {
             commands
                 .entity(*wheel_id)
                 .insert(MultibodyJoint::new(car, joints[i]));
         }
 
-        if config.use_brain {
-            let brain = match saved_brain {
-                Some(ref b) => CarBrain::clone_randomised(&b),
-                None => CarBrain::new(config.sensor_count),
+        if config.use_brain && !human {
+            let mutation = trainer.ga.mutation;
+            let brain = if !start_brains.brains.is_empty() {
+                let b = &start_brains.brains[i % start_brains.brains.len()];
+                match start_brains.mutate {
+                    true => CarBrain::clone_randomised(b, mutation, &mut rng.rng),
+                    false => b.clone(),
+                }
+            } else {
+                match saved_brain {
+                    Some(ref b) => CarBrain::clone_randomised(b, mutation, &mut rng.rng),
+                    None => CarBrain::new(config.brain_inputs(), &mut rng.rng),
+                }
             };
             // println!("br {:?}", brain.levels.clone());
             commands.entity(car).insert(brain);
         }
     }
 }
+
+pub fn car_collisions_key_system(
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<Config>,
+    mut q_body: Query<&mut CollisionGroups, With<CarBody>>,
+) {
+    if keys.just_pressed(KeyCode::G) {
+        config.car_collisions = !config.car_collisions;
+        println!("car collisions {:?}", config.car_collisions);
+        for mut groups in q_body.iter_mut() {
+            *groups = car_body_groups(config.car_collisions);
+        }
+    }
+}
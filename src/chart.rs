@@ -0,0 +1,292 @@
+use crate::metrics::MetricsHistory;
+use bevy::prelude::*;
+
+#[derive(Component)]
+pub struct Chart {
+    pub size: Vec2,
+    pub segments: usize,
+    pub log_scale: bool,
+    pub names: Vec<String>,
+}
+
+#[derive(Component)]
+pub struct ChartSegment {
+    pub chart: Entity,
+    pub series: usize,
+    pub index: usize,
+}
+
+#[derive(Component)]
+pub struct ChartLabel {
+    pub chart: Entity,
+}
+
+#[derive(Component)]
+pub struct LearningCurve;
+
+pub fn spawn_chart(
+    commands: &mut Commands,
+    font: Handle<Font>,
+    position: UiRect<Val>,
+    size: Vec2,
+    series: &[(&str, Color)],
+    segments: usize,
+) -> Entity {
+    let chart = commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position,
+                size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+                ..default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
+            ..default()
+        })
+        .insert(Chart {
+            size,
+            segments,
+            log_scale: false,
+            names: series.iter().map(|(name, _)| name.to_string()).collect(),
+        })
+        .id();
+    commands.entity(chart).with_children(|parent| {
+        for (i, (_, color)) in series.iter().enumerate() {
+            for index in 0..segments {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            size: Size::new(Val::Px(0.), Val::Px(2.)),
+                            ..default()
+                        },
+                        color: UiColor(*color),
+                        ..default()
+                    })
+                    .insert(ChartSegment {
+                        chart,
+                        series: i,
+                        index,
+                    });
+            }
+        }
+        parent
+            .spawn_bundle(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(2.),
+                        left: Val::Px(4.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font,
+                        font_size: 12.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                ..default()
+            })
+            .insert(ChartLabel { chart });
+    });
+    chart
+}
+
+fn symlog(v: f32) -> f32 {
+    v.signum() * v.abs().ln_1p()
+}
+
+pub fn chart_points(chart: &Chart, series: &[Vec<f32>]) -> Vec<Vec<Vec2>> {
+    let scaled: Vec<Vec<f32>> = series
+        .iter()
+        .map(|values| {
+            let n = values.len();
+            let n_points = n.min(chart.segments + 1);
+            (0..n_points)
+                .map(|j| match n_points {
+                    1 => values[0],
+                    _ => values[(j * (n - 1) + (n_points - 1) / 2) / (n_points - 1)],
+                })
+                .map(|v| if chart.log_scale { symlog(v) } else { v })
+                .collect()
+        })
+        .collect();
+    let min = scaled.iter().flatten().cloned().fold(f32::MAX, f32::min);
+    let max = scaled.iter().flatten().cloned().fold(f32::MIN, f32::max);
+    let range = if max > min { max - min } else { 1. };
+    scaled
+        .iter()
+        .map(|values| {
+            let step = chart.size.x / (values.len().max(2) - 1) as f32;
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| Vec2::new(i as f32 * step, (v - min) / range * chart.size.y))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn draw_chart(
+    chart_e: Entity,
+    points: &[Vec<Vec2>],
+    q_segments: &mut Query<(&ChartSegment, &mut Style, &mut Transform)>,
+) {
+    for (segment, mut style, mut transform) in q_segments.iter_mut() {
+        if segment.chart != chart_e {
+            continue;
+        }
+        let series = &points[segment.series];
+        if segment.index + 1 >= series.len() {
+            style.size.width = Val::Px(0.);
+            continue;
+        }
+        let a = series[segment.index];
+        let b = series[segment.index + 1];
+        place_segment(&mut style, &mut transform, a, b, 2.);
+    }
+}
+
+// stretches and rotates a ui node into a line from a to b, bottom-left origin
+pub fn place_segment(style: &mut Style, transform: &mut Transform, a: Vec2, b: Vec2, width: f32) {
+    let d = b - a;
+    let length = d.length();
+    let mid = (a + b) / 2.;
+    style.size = Size::new(Val::Px(length), Val::Px(width));
+    style.position.left = Val::Px(mid.x - length / 2.);
+    style.position.bottom = Val::Px(mid.y - width / 2.);
+    transform.rotation = Quat::from_rotation_z(d.y.atan2(d.x));
+}
+
+// index of the value under the cursor, if the cursor is over the chart
+pub fn chart_hover(
+    windows: &Windows,
+    chart: &Chart,
+    node: &Node,
+    transform: &GlobalTransform,
+    n_values: usize,
+) -> Option<usize> {
+    let cursor = windows.get_primary()?.cursor_position()?;
+    let min = transform.translation().truncate() - node.size / 2.;
+    let local = cursor - min;
+    if n_values == 0
+        || local.x < 0.
+        || local.y < 0.
+        || local.x > node.size.x
+        || local.y > node.size.y
+    {
+        return None;
+    }
+    let x = local.x / chart.size.x;
+    Some(((x * (n_values - 1) as f32).round() as usize).min(n_values - 1))
+}
+
+pub fn learning_curve_start_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    let chart = spawn_chart(
+        &mut commands,
+        medium,
+        UiRect {
+            bottom: Val::Px(100.0),
+            right: Val::Px(15.0),
+            ..default()
+        },
+        Vec2::new(300., 150.),
+        &[("best", Color::GOLD), ("mean", Color::CYAN)],
+        100,
+    );
+    commands.entity(chart).insert(LearningCurve);
+}
+
+pub fn learning_curve_system(
+    history: Res<MetricsHistory>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    mut q_chart: Query<(Entity, &mut Chart, &Node, &GlobalTransform), With<LearningCurve>>,
+    mut q_segments: Query<(&ChartSegment, &mut Style, &mut Transform)>,
+    mut q_label: Query<(&ChartLabel, &mut Text)>,
+) {
+    let (chart_e, mut chart, node, transform) = q_chart.single_mut();
+    let toggled = keys.just_pressed(KeyCode::L);
+    if toggled {
+        chart.log_scale = !chart.log_scale;
+    }
+    let best: Vec<f32> = history.generations.iter().map(|g| g.best).collect();
+    let mean: Vec<f32> = history.generations.iter().map(|g| g.mean).collect();
+    if toggled || history.is_changed() {
+        let points = chart_points(&chart, &[best.clone(), mean.clone()]);
+        draw_chart(chart_e, &points, &mut q_segments);
+    }
+
+    let n = history.generations.len();
+    let i = match chart_hover(&windows, &chart, node, transform, n) {
+        Some(i) => i,
+        None if n > 0 => n - 1,
+        None => return,
+    };
+    for (label, mut text) in q_label.iter_mut() {
+        if label.chart != chart_e {
+            continue;
+        }
+        text.sections[0].value = format!(
+            "gen {} {} {:.1} {} {:.1}{}",
+            history.generations[i].generation,
+            chart.names[0],
+            best[i],
+            chart.names[1],
+            mean[i],
+            if chart.log_scale { " log" } else { "" }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(segments: usize) -> Chart {
+        Chart {
+            size: Vec2::new(100., 50.),
+            segments,
+            log_scale: false,
+            names: vec![],
+        }
+    }
+
+    #[test]
+    fn series_share_one_scale() {
+        let points = chart_points(&chart(10), &[vec![0., 5., 10.], vec![10., 20.]]);
+        assert_eq!(
+            points[0],
+            vec![
+                Vec2::new(0., 0.),
+                Vec2::new(50., 12.5),
+                Vec2::new(100., 25.)
+            ]
+        );
+        assert_eq!(points[1], vec![Vec2::new(0., 25.), Vec2::new(100., 50.)]);
+    }
+
+    #[test]
+    fn long_series_are_resampled_keeping_both_ends() {
+        let values: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let points = chart_points(&chart(4), &[values]);
+        let xs: Vec<f32> = points[0].iter().map(|p| p.x).collect();
+        let ys: Vec<f32> = points[0].iter().map(|p| p.y).collect();
+        assert_eq!(xs, vec![0., 25., 50., 75., 100.]);
+        assert_eq!(ys[0], 0.);
+        assert_eq!(ys[4], 50.);
+        assert!((ys[2] - 50. * 50. / 99.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_flat_series_stays_at_the_bottom() {
+        let points = chart_points(&chart(10), &[vec![3., 3., 3.]]);
+        assert!(points[0].iter().all(|p| p.y == 0.));
+        assert!(chart_points(&chart(10), &[vec![]])[0].is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    brain::*,
+    curriculum::*,
+    fitness::{Fitness, FitnessWeights},
+    noise::Noise,
+    rng::*,
+    trainer::*,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+// 2 keeps the population of every world by car id
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub generation: i32,
+    pub rng: RngState,
+    pub ga: GaConfig,
+    pub fitness: FitnessWeights,
+    pub noise: Noise,
+    pub best_brain: Option<CarBrain>,
+    // by world, then car id
+    pub populations: Vec<Vec<CarBrain>>,
+    pub curriculum: Option<CurriculumState>,
+}
+
+pub fn checkpoint_dir() -> PathBuf {
+    Path::new("checkpoints").join(format!("v{}", CHECKPOINT_VERSION))
+}
+
+impl Checkpoint {
+    pub fn new(
+        trainer: &Trainer,
+        rng: &SimRng,
+        fitness: &Fitness,
+        noise: &Noise,
+        curriculum: Option<&Curriculum>,
+    ) -> Self {
+        Self {
+            version: CHECKPOINT_VERSION,
+            generation: trainer.generation,
+            rng: rng.state(),
+            ga: trainer.ga.clone(),
+            fitness: fitness.weights.clone(),
+            noise: noise.clone(),
+            best_brain: trainer.best_brain.clone(),
+            populations: trainer.populations.clone(),
+            curriculum: curriculum.map(|c| c.state()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("unable to open checkpoint {}: {}", path.display(), e))?;
+        let checkpoint: Checkpoint = serde_json::from_reader(file)
+            .map_err(|e| format!("unable to parse checkpoint {}: {}", path.display(), e))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "checkpoint {} is version {}, expected {}",
+                path.display(),
+                checkpoint.version,
+                CHECKPOINT_VERSION
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn save(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("gen-{:06}.json", self.generation));
+        let serialized = serde_json::to_string(self)?;
+        fs::write(&path, serialized)?;
+        Ok(path)
+    }
+
+    // brains for other sensors than the config's are rejected before anything is restored
+    pub fn restore(
+        &self,
+        n_inputs: usize,
+        trainer: &mut Trainer,
+        rng: &mut SimRng,
+        fitness: &mut Fitness,
+        noise: &mut Noise,
+        start_brains: &mut StartBrains,
+        curriculum: Option<&mut Curriculum>,
+    ) -> Result<(), String> {
+        let brains = self
+            .populations
+            .iter()
+            .flatten()
+            .chain(self.best_brain.iter());
+        for brain in brains {
+            let inputs = brain.topology().first().cloned().unwrap_or(0);
+            if inputs != n_inputs {
+                return Err(format!(
+                    "checkpoint brains take {} inputs, the sensors give {}",
+                    inputs, n_inputs
+                ));
+            }
+        }
+        trainer.ga = self.ga.clone();
+        trainer.generation = self.generation;
+        // the next generation starts from 0 as it would live
+        trainer.record = 0.;
+        trainer.best_brain = self.best_brain.clone();
+        trainer.populations = self.populations.clone();
+        *rng = SimRng::from_state(&self.rng);
+        *fitness = Fitness::new(self.fitness.clone());
+        *noise = self.noise.clone();
+        start_brains.brains = self.populations.first().cloned().unwrap_or_default();
+        start_brains.mutate = false;
+        start_brains.worlds = self.populations.clone();
+        if let (Some(curriculum), Some(state)) = (curriculum, &self.curriculum) {
+            curriculum.restore(state);
+        }
+        Ok(())
+    }
+}
+
+pub fn checkpoint_system(
+    mut e_generation: EventReader<GenerationEvent>,
+    trainer: Res<Trainer>,
+    rng: Res<SimRng>,
+    fitness: Res<Fitness>,
+    noise: Res<Noise>,
+    curriculum: Option<Res<Curriculum>>,
+) {
+    for generation_e in e_generation.iter() {
+        if trainer.ga.checkpoint_every <= 0
+            || generation_e.generation % trainer.ga.checkpoint_every != 0
+        {
+            continue;
+        }
+        let checkpoint = Checkpoint::new(&trainer, &rng, &fitness, &noise, curriculum.as_deref());
+        match checkpoint.save(&checkpoint_dir()) {
+            Ok(path) => println!("checkpoint saved {}", path.display()),
+            Err(e) => println!("unable to save checkpoint: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, noise::NoiseConfig};
+    use rand::Rng;
+
+    fn weights(brain: &CarBrain) -> Vec<f32> {
+        brain
+            .levels
+            .iter()
+            .flat_map(|l| l.weights.iter().chain(l.biases.iter()).cloned())
+            .collect()
+    }
+
+    fn saved() -> (Checkpoint, SimRng) {
+        let mut rng = SimRng::new(9);
+        let mut brain = || CarBrain::new(5, &mut rng.rng);
+        let trainer = Trainer {
+            generation: 12,
+            best_brain: Some(brain()),
+            populations: vec![vec![brain(), brain()], vec![brain()]],
+            ..default()
+        };
+        let mut curriculum = Curriculum::new(&Config::default());
+        curriculum.level = 2;
+        curriculum.scores.insert(3, vec![(10., 0.4)]);
+        let noise = Noise {
+            sensor: NoiseConfig {
+                dropout: 0.2,
+                ..default()
+            },
+            ..default()
+        };
+        let fitness = Fitness::new(FitnessWeights {
+            progress: 5.,
+            ..default()
+        });
+        let checkpoint = Checkpoint::new(&trainer, &rng, &fitness, &noise, Some(&curriculum));
+        (checkpoint, rng)
+    }
+
+    #[test]
+    fn save_and_restore_round_trip() {
+        let (checkpoint, mut rng) = saved();
+        let dir = std::env::temp_dir().join(format!("car-sim-{}-checkpoints", std::process::id()));
+        let path = checkpoint.save(&dir).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut trainer = Trainer::default();
+        let mut restored_rng = SimRng::new(0);
+        let mut fitness = Fitness::default();
+        let mut noise = Noise::default();
+        let mut start_brains = StartBrains::default();
+        let mut curriculum = Curriculum::new(&Config::default());
+        loaded
+            .restore(
+                5,
+                &mut trainer,
+                &mut restored_rng,
+                &mut fitness,
+                &mut noise,
+                &mut start_brains,
+                Some(&mut curriculum),
+            )
+            .unwrap();
+
+        assert_eq!(trainer.generation, 12);
+        let best = |c: &Checkpoint| weights(c.best_brain.as_ref().unwrap());
+        assert_eq!(
+            weights(trainer.best_brain.as_ref().unwrap()),
+            best(&checkpoint)
+        );
+        for (world, population) in checkpoint.populations.iter().enumerate() {
+            let restored = start_brains.of_world(world);
+            assert!(!restored.mutate);
+            let restored: Vec<Vec<f32>> = restored.brains.iter().map(weights).collect();
+            let saved: Vec<Vec<f32>> = population.iter().map(weights).collect();
+            assert_eq!(restored, saved);
+        }
+        assert_eq!(restored_rng.rng.gen::<u64>(), rng.rng.gen::<u64>());
+        assert_eq!(fitness.weights.progress, 5.);
+        assert_eq!(noise.sensor.dropout, 0.2);
+        assert_eq!(curriculum.state(), checkpoint.curriculum.unwrap());
+    }
+
+    #[test]
+    fn restore_rejects_brains_for_other_sensors() {
+        let (checkpoint, _) = saved();
+        let mut trainer = Trainer::default();
+        let result = checkpoint.restore(
+            7,
+            &mut trainer,
+            &mut SimRng::new(0),
+            &mut Fitness::default(),
+            &mut Noise::default(),
+            &mut StartBrains::default(),
+            None,
+        );
+        assert!(result.is_err());
+        assert_eq!(trainer.generation, 0);
+    }
+}
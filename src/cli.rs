@@ -0,0 +1,112 @@
+use crate::{input::MAX_PLAYERS, library::BrainCommand, metrics::MetricsFormat};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub resume: Option<PathBuf>,
+    pub metrics: Option<MetricsFormat>,
+    pub brains: Vec<String>,
+    pub noise: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub weights: Option<PathBuf>,
+    pub termination: Option<PathBuf>,
+    pub worlds: usize,
+    pub curriculum: bool,
+    pub car_sensors: bool,
+    pub race: bool,
+    pub players: usize,
+    pub port: Option<u16>,
+    pub connect: Option<SocketAddr>,
+    pub brain_command: Option<BrainCommand>,
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut args = Args::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--resume" => match iter.next() {
+                    Some(path) => args.resume = Some(PathBuf::from(path)),
+                    None => exit_with("--resume requires a checkpoint path"),
+                },
+                "--metrics" => match iter.next().as_deref() {
+                    Some("csv") => args.metrics = Some(MetricsFormat::Csv),
+                    Some("jsonl") => args.metrics = Some(MetricsFormat::Jsonl),
+                    _ => exit_with("--metrics requires csv or jsonl"),
+                },
+                "--noise" => match iter.next() {
+                    Some(path) => args.noise = Some(PathBuf::from(path)),
+                    None => exit_with("--noise requires a noise json path"),
+                },
+                "--seed" => match iter.next().and_then(|n| n.parse().ok()) {
+                    Some(seed) => args.seed = Some(seed),
+                    None => exit_with("--seed requires a number"),
+                },
+                "--weights" => match iter.next() {
+                    Some(path) => args.weights = Some(PathBuf::from(path)),
+                    None => exit_with("--weights requires a fitness weights json path"),
+                },
+                "--termination" => match iter.next() {
+                    Some(path) => args.termination = Some(PathBuf::from(path)),
+                    None => exit_with("--termination requires a termination rules json path"),
+                },
+                "--worlds" => match iter.next().and_then(|n| n.parse().ok()) {
+                    Some(n) if n > 0 => args.worlds = n,
+                    _ => exit_with("--worlds requires a positive number of worlds"),
+                },
+                "--curriculum" => args.curriculum = true,
+                "--car-sensors" => args.car_sensors = true,
+                "--race" => args.race = true,
+                "--players" => match iter.next().and_then(|n| n.parse().ok()) {
+                    Some(n) if (1..=MAX_PLAYERS).contains(&n) => args.players = n,
+                    _ => exit_with(&format!("--players requires 1 to {} players", MAX_PLAYERS)),
+                },
+                "--port" => match iter.next().and_then(|n| n.parse().ok()) {
+                    Some(port) => args.port = Some(port),
+                    None => exit_with("--port requires a port number"),
+                },
+                "--connect" => match iter
+                    .next()
+                    .and_then(|a| a.to_socket_addrs().ok())
+                    .and_then(|mut addrs| addrs.next())
+                {
+                    Some(addr) => args.connect = Some(addr),
+                    None => exit_with("--connect requires a server address like localhost:7878"),
+                },
+                "--brains" => match iter.next() {
+                    Some(names) => args.brains = names.split(',').map(String::from).collect(),
+                    None => exit_with("--brains requires comma separated library names"),
+                },
+                "brain" => {
+                    let op = iter.next();
+                    let a = iter.next();
+                    let b = iter.next();
+                    args.brain_command = Some(match (op.as_deref(), a, b) {
+                        (Some("list"), None, None) => BrainCommand::List,
+                        (Some("load"), Some(a), None) => BrainCommand::Load(a),
+                        (Some("rename"), Some(a), Some(b)) => BrainCommand::Rename(a, b),
+                        (Some("delete"), Some(a), None) => BrainCommand::Delete(a),
+                        (Some("compare"), Some(a), Some(b)) => BrainCommand::Compare(a, b),
+                        (Some("convert"), Some(a), Some(b)) => {
+                            BrainCommand::Convert(PathBuf::from(a), PathBuf::from(b))
+                        }
+                        _ => exit_with(
+                            "usage: brain list | load <name> | rename <from> <to> | delete <name> | compare <a> <b> | convert <from> <to>",
+                        ),
+                    });
+                }
+                _ => exit_with(&format!("unknown argument {}", arg)),
+            }
+        }
+        args
+    }
+}
+
+pub fn exit_with(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
@@ -1,18 +1,35 @@
+use crate::brain_file::SensorLayout;
 use bevy::prelude::*;
 use parry3d::shape::Polyline;
 use std::f32::consts::PI;
 
+// which car the camera follows, the camera resolves it to camera_follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowMode {
+    Car,
+    Leader,
+    BestBrain,
+}
+
 pub struct Config {
+    pub track_name: String,
     pub translation: Vec3,
     pub quat: Quat,
     pub cars_count: usize,
+    pub players: usize,
     pub sensor_count: usize,
+    pub car_sensors: bool,
     pub show_rays: bool,
+    pub rays_all_cars: bool,
     pub use_brain: bool,
+    pub race_mode: bool,
+    pub car_collisions: bool,
     pub max_torque: f32,
     pub max_toi: f32,
+    pub road_half_width: f32,
     pub hid_car: Option<Entity>,
     pub camera_follow: Option<Entity>,
+    pub follow_mode: FollowMode,
     pub polyline: Option<Polyline>,
     pub segment_i: u32,
     pub segment_m: f32,
@@ -20,21 +37,30 @@ pub struct Config {
     pub meters_shift: f32,
     pub meters_total: f32,
     pub reset_pause_until: f64,
+    pub seed: u64,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            track_name: "nurburgring-gp".to_string(),
             cars_count: 20,
+            players: 1,
             use_brain: true,
+            race_mode: false,
+            car_collisions: false,
             show_rays: true,
+            rays_all_cars: false,
             sensor_count: 7,
+            car_sensors: false,
             max_torque: 600.,
             max_toi: 50.,
+            road_half_width: 8.,
             translation: Vec3::new(0., 0.9, 0.),
             quat: Quat::from_rotation_y(-PI * 0.225),
             hid_car: None,
             camera_follow: None,
+            follow_mode: FollowMode::Car,
             polyline: None,
             segment_i: 0,
             segment_m: 0.,
@@ -42,6 +68,20 @@ impl Default for Config {
             meters_shift: 0.,
             meters_total: 0.,
             reset_pause_until: 0.,
+            seed: 42,
         }
     }
 }
+
+impl Config {
+    pub fn sensor_layout(&self) -> SensorLayout {
+        SensorLayout {
+            sensor_count: self.sensor_count,
+            car_sensors: self.car_sensors,
+            max_toi: self.max_toi,
+        }
+    }
+    pub fn brain_inputs(&self) -> usize {
+        self.sensor_layout().inputs()
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{car::CarBody, track::Wall};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+// lighter touches are not worth a fitness penalty
+const CONTACT_FORCE_THRESHOLD: f32 = 1000.;
+
+#[derive(Debug, Clone)]
+pub struct CarContact {
+    pub at: f64,
+    pub other: Entity,
+}
+
+#[derive(Component, Debug, Default)]
+pub struct CarContacts {
+    pub cars: Vec<CarContact>,
+    pub walls: usize,
+    pub force: f32,
+}
+
+impl CarContacts {
+    pub fn clear(&mut self) {
+        self.cars.clear();
+        self.walls = 0;
+        self.force = 0.;
+    }
+    pub fn count(&self) -> usize {
+        self.cars.len() + self.walls
+    }
+}
+
+pub fn car_contacts_system(
+    time: Res<Time>,
+    mut e_collision: EventReader<CollisionEvent>,
+    q_body: Query<&Parent, With<CarBody>>,
+    q_wall: Query<Entity, With<Wall>>,
+    mut q_contacts: Query<&mut CarContacts>,
+) {
+    let seconds = time.seconds_since_startup();
+    for collision_e in e_collision.iter() {
+        if let CollisionEvent::Started(c1, c2, _) = collision_e {
+            let car1 = q_body.get(*c1).ok().map(|p| p.get());
+            let car2 = q_body.get(*c2).ok().map(|p| p.get());
+            match (car1, car2) {
+                (Some(a), Some(b)) => {
+                    println!("car contact {:?} {:?}", a, b);
+                    for (car, other) in [(a, b), (b, a)] {
+                        if let Ok(mut contacts) = q_contacts.get_mut(car) {
+                            contacts.cars.push(CarContact { at: seconds, other });
+                        }
+                    }
+                }
+                (Some(car), None) | (None, Some(car)) => {
+                    let other = if car1.is_some() { *c2 } else { *c1 };
+                    if q_wall.get(other).is_ok() {
+                        if let Ok(mut contacts) = q_contacts.get_mut(car) {
+                            contacts.walls += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// the rapier version we are on has no contact force events, read the impulses off the narrow phase
+pub fn car_contact_forces_system(
+    time: Res<Time>,
+    context: Res<RapierContext>,
+    q_body: Query<(Entity, &Parent), With<CarBody>>,
+    mut q_contacts: Query<&mut CarContacts>,
+) {
+    let dt = time.delta_seconds();
+    if dt <= 0. {
+        return;
+    }
+    for (e, parent) in q_body.iter() {
+        let handle = match context.entity2collider().get(&e) {
+            Some(handle) => *handle,
+            None => continue,
+        };
+        let impulse: f32 = context
+            .narrow_phase
+            .contacts_with(handle)
+            .map(|pair| pair.total_impulse_magnitude())
+            .filter(|impulse| impulse / dt > CONTACT_FORCE_THRESHOLD)
+            .sum();
+        if impulse > 0. {
+            if let Ok(mut contacts) = q_contacts.get_mut(parent.get()) {
+                contacts.force += impulse;
+            }
+        }
+    }
+}
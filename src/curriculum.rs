@@ -0,0 +1,206 @@
+use crate::{car::*, config::Config, progress::*, track::*, trainer::CarResult};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// rotates the population through track variants, every car drives all unlocked
+// tracks with the same brain before the generation is evaluated
+#[derive(Clone)]
+pub struct Curriculum {
+    pub tracks: Vec<TrackVariant>,
+    // tracks[..=level] are in the rotation
+    pub level: usize,
+    // mean share of the lap the population needs to unlock the next track
+    pub threshold: f32,
+    pub active: usize,
+    pub spawned: usize,
+    pub base_translation: Vec3,
+    pub base_quat: Quat,
+    // fitness and share of the lap per car id for every track driven this generation
+    pub scores: HashMap<usize, Vec<(f32, f32)>>,
+}
+
+// what a checkpoint keeps of the curriculum, the tracks come from the config
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CurriculumState {
+    pub level: usize,
+    pub scores: HashMap<usize, Vec<(f32, f32)>>,
+}
+
+impl Curriculum {
+    pub fn new(config: &Config) -> Self {
+        let name = &config.track_name;
+        Self {
+            tracks: vec![
+                TrackVariant::new(name, false, false),
+                TrackVariant::new(&format!("{}-mirrored", name), true, false),
+                TrackVariant::new(&format!("{}-reversed", name), false, true),
+                TrackVariant::new(&format!("{}-mirrored-reversed", name), true, true),
+            ],
+            level: 0,
+            threshold: 0.5,
+            active: 0,
+            spawned: 0,
+            base_translation: config.translation,
+            base_quat: config.quat,
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn state(&self) -> CurriculumState {
+        CurriculumState {
+            level: self.level,
+            scores: self.scores.clone(),
+        }
+    }
+
+    // checkpoints are saved between generations, the rotation starts over
+    pub fn restore(&mut self, state: &CurriculumState) {
+        self.level = state.level.min(self.tracks.len() - 1);
+        self.scores = state.scores.clone();
+        self.active = 0;
+    }
+
+    pub fn track(&self) -> &TrackVariant {
+        &self.tracks[self.active]
+    }
+
+    // returns the next track of the rotation, None when the generation is complete
+    pub fn record(&mut self, results: &[CarResult], meters_total: f32) -> Option<usize> {
+        for r in results.iter() {
+            let share = r.meters / meters_total.max(1.);
+            self.scores
+                .entry(r.id)
+                .or_default()
+                .push((r.fitness, share));
+        }
+        match self.active < self.level {
+            true => Some(self.active + 1),
+            false => None,
+        }
+    }
+
+    // replaces fitness with the mean over the rotation and advances the difficulty
+    pub fn finish(&mut self, results: &mut [CarResult]) -> usize {
+        let mut share_sum = 0.;
+        for r in results.iter_mut() {
+            if let Some(scores) = self.scores.get(&r.id) {
+                let n = scores.len() as f32;
+                r.fitness = scores.iter().map(|s| s.0).sum::<f32>() / n;
+                share_sum += scores.iter().map(|s| s.1).sum::<f32>() / n;
+            }
+        }
+        let share = share_sum / results.len().max(1) as f32;
+        if share >= self.threshold && self.level + 1 < self.tracks.len() {
+            self.level += 1;
+            println!(
+                "curriculum level {}, {} unlocked at {:.2} of a lap",
+                self.level, self.tracks[self.level].name, share
+            );
+        }
+        self.scores.clear();
+        0
+    }
+}
+
+// name of the track the cars drive now, the curriculum variant when there is one
+pub fn active_track_name(config: &Config, curriculum: Option<&Curriculum>) -> String {
+    match curriculum {
+        Some(curriculum) => curriculum.track().name.clone(),
+        None => config.track_name.clone(),
+    }
+}
+
+pub fn curriculum_track_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut config: ResMut<Config>,
+    mut curriculum: ResMut<Curriculum>,
+    q_parts: Query<Entity, With<TrackPart>>,
+    mut q_cars: Query<(&CarId, &mut Car, &mut Transform)>,
+) {
+    if curriculum.spawned == curriculum.active {
+        return;
+    }
+    for e in q_parts.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    let variant = curriculum.track().clone();
+    let (translation, quat) = variant.start(curriculum.base_translation, curriculum.base_quat);
+    config.translation = translation;
+    config.quat = quat;
+    spawn_track(&mut commands, &mut meshes, &mut materials, &variant);
+    spawn_track_polyline(&mut commands, &mut config, &variant);
+    for (id, mut car, mut t) in q_cars.iter_mut() {
+        car.init_transform = car_start_transform(&config, id.0);
+        *t = car.init_transform;
+    }
+    curriculum.spawned = curriculum.active;
+    println!("track {}", variant.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(id: usize, fitness: f32, meters: f32) -> CarResult {
+        CarResult {
+            id,
+            fitness,
+            breakdown: vec![],
+            meters,
+            lap_times: vec![],
+            termination: None,
+        }
+    }
+
+    #[test]
+    fn record_rotates_through_the_unlocked_tracks() {
+        let mut curriculum = Curriculum::new(&Config::default());
+        assert_eq!(curriculum.record(&[result(0, 1., 100.)], 1000.), None);
+        curriculum.scores.clear();
+        curriculum.level = 2;
+        assert_eq!(curriculum.record(&[result(0, 1., 100.)], 1000.), Some(1));
+        curriculum.active = 2;
+        assert_eq!(curriculum.record(&[result(0, 1., 100.)], 1000.), None);
+        assert_eq!(curriculum.scores[&0], vec![(1., 0.1), (1., 0.1)]);
+    }
+
+    #[test]
+    fn finish_averages_fitness_over_the_rotation() {
+        let mut curriculum = Curriculum::new(&Config::default());
+        curriculum.level = 1;
+        curriculum.record(&[result(0, 10., 200.), result(1, 2., 100.)], 1000.);
+        curriculum.active = 1;
+        curriculum.record(&[result(0, 20., 400.), result(1, 4., 100.)], 1000.);
+        let mut results = vec![result(0, 20., 400.), result(1, 4., 100.)];
+        assert_eq!(curriculum.finish(&mut results), 0);
+        assert_eq!(results[0].fitness, 15.);
+        assert_eq!(results[1].fitness, 3.);
+        // a mean share of 0.2 keeps the level
+        assert_eq!(curriculum.level, 1);
+        assert!(curriculum.scores.is_empty());
+    }
+
+    #[test]
+    fn half_a_lap_unlocks_the_next_track() {
+        let mut curriculum = Curriculum::new(&Config::default());
+        curriculum.record(&[result(0, 1., 499.)], 1000.);
+        curriculum.finish(&mut [result(0, 1., 499.)]);
+        assert_eq!(curriculum.level, 0);
+        curriculum.record(&[result(0, 1., 500.)], 1000.);
+        curriculum.finish(&mut [result(0, 1., 500.)]);
+        assert_eq!(curriculum.level, 1);
+    }
+
+    #[test]
+    fn the_last_track_is_not_passed() {
+        let mut curriculum = Curriculum::new(&Config::default());
+        let last = curriculum.tracks.len() - 1;
+        curriculum.level = last;
+        curriculum.record(&[result(0, 1., 1000.)], 1000.);
+        curriculum.finish(&mut [result(0, 1., 1000.)]);
+        assert_eq!(curriculum.level, last);
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{car::*, progress::*, trainer::*};
+use crate::{
+    car::*,
+    config::{Config, FollowMode},
+    fitness::CarStats,
+    progress::*,
+    race::*,
+    termination::*,
+    trainer::*,
+};
 use bevy::prelude::*;
 use bevy::{diagnostic::Diagnostics, diagnostic::FrameTimeDiagnosticsPlugin};
 use bevy_rapier3d::prelude::*;
@@ -21,6 +29,15 @@ pub struct WheelsTorqueText;
 #[derive(Component)]
 pub struct Leaderboard;
 
+#[derive(Component)]
+pub struct LeaderboardRow {
+    pub index: usize,
+    pub car: Option<Entity>,
+}
+
+const LEADERBOARD_ROW: f32 = 14.;
+const LEADERBOARD_HEADER: &str = "P  car     lap     gap    last    best status";
+
 pub fn dash_fps_start_system(mut commands: Commands, asset_server: Res<AssetServer>) {
     let bold: Handle<Font> = asset_server.load("fonts/FiraSans-Bold.ttf");
     let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
@@ -75,7 +92,7 @@ pub fn dash_fps_start_system(mut commands: Commands, asset_server: Res<AssetServ
             text: Text {
                 sections: vec![
                     TextSection {
-                        value: "distance record: ".to_string(),
+                        value: "fitness record: ".to_string(),
                         style: TextStyle {
                             font: bold.clone(),
                             font_size: 16.0,
@@ -138,7 +155,7 @@ pub fn dash_fps_start_system(mut commands: Commands, asset_server: Res<AssetServ
                 align_self: AlignSelf::FlexEnd,
                 position_type: PositionType::Absolute,
                 position: UiRect {
-                    bottom: Val::Px(2.0),
+                    top: Val::Px(2.0),
                     left: Val::Px(2.0),
                     ..default()
                 },
@@ -147,7 +164,7 @@ pub fn dash_fps_start_system(mut commands: Commands, asset_server: Res<AssetServ
             text: Text {
                 sections: vec![
                     TextSection {
-                        value: "leaderboard: ".to_string(),
+                        value: "FPS: ".to_string(),
                         style: TextStyle {
                             font: bold.clone(),
                             font_size: 16.0,
@@ -167,56 +184,177 @@ pub fn dash_fps_start_system(mut commands: Commands, asset_server: Res<AssetServ
             },
             ..default()
         })
-        .insert(Leaderboard);
+        .insert(FpsText);
+}
+
+pub fn dash_leaderboard_start_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<Config>,
+) {
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    let row_text = |value: &str, color: Color| TextBundle {
+        text: Text::from_section(
+            value,
+            TextStyle {
+                font: medium.clone(),
+                font_size: 12.0,
+                color,
+            },
+        ),
+        ..default()
+    };
+    let rows = config.cars_count + 1;
     commands
-        .spawn_bundle(TextBundle {
+        .spawn_bundle(NodeBundle {
             style: Style {
-                align_self: AlignSelf::FlexEnd,
                 position_type: PositionType::Absolute,
                 position: UiRect {
-                    top: Val::Px(2.0),
+                    bottom: Val::Px(82.0),
                     left: Val::Px(2.0),
                     ..default()
                 },
+                size: Size::new(Val::Px(360.), Val::Px(rows as f32 * LEADERBOARD_ROW)),
                 ..default()
             },
-            text: Text {
-                sections: vec![
-                    TextSection {
-                        value: "FPS: ".to_string(),
-                        style: TextStyle {
-                            font: bold.clone(),
-                            font_size: 16.0,
-                            color: Color::WHITE,
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
+            ..default()
+        })
+        .insert(Name::new("Leaderboard"))
+        .insert(Leaderboard)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: UiRect {
+                            top: Val::Px(0.),
+                            left: Val::Px(4.),
+                            ..default()
                         },
+                        ..default()
                     },
-                    TextSection {
-                        value: "".to_string(),
-                        style: TextStyle {
-                            font: medium.clone(),
-                            font_size: 16.0,
-                            color: Color::GOLD,
+                    color: UiColor(Color::NONE),
+                    ..default()
+                })
+                .with_children(|header| {
+                    header.spawn_bundle(row_text(LEADERBOARD_HEADER, Color::WHITE));
+                });
+            for index in 0..config.cars_count {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                top: Val::Px((index + 1) as f32 * LEADERBOARD_ROW),
+                                left: Val::Px(0.),
+                                ..default()
+                            },
+                            size: Size::new(Val::Percent(100.), Val::Px(LEADERBOARD_ROW)),
+                            padding: UiRect {
+                                left: Val::Px(4.),
+                                ..default()
+                            },
+                            ..default()
                         },
-                    },
-                ],
-                ..default()
-            },
-            ..default()
-        })
-        .insert(FpsText);
+                        color: UiColor(Color::NONE),
+                        ..default()
+                    })
+                    .insert(LeaderboardRow { index, car: None })
+                    .with_children(|row| {
+                        row.spawn_bundle(row_text("", Color::GOLD));
+                    });
+            }
+        });
+}
+
+fn lap_time(t: Option<f32>) -> String {
+    t.map_or("-".to_string(), |t| format!("{:.2}", t))
 }
 
 pub fn dash_leaderboard_system(
-    q_cars: Query<&CarProgress, With<CarProgress>>,
-    mut q_leaderboard: Query<&mut Text, With<Leaderboard>>,
+    config: Res<Config>,
+    race: Res<Race>,
+    q_cars: Query<
+        (
+            Entity,
+            &Name,
+            &CarProgress,
+            &CarStats,
+            Option<&CarTermination>,
+        ),
+        With<Car>,
+    >,
+    mut q_rows: Query<(&mut LeaderboardRow, &mut UiColor, &Children)>,
+    mut q_text: Query<&mut Text>,
 ) {
-    let mut text_string: String = "".to_string();
-    for progress in q_cars.iter() {
-        text_string = text_string + &progress.meters.round().to_string() + " ";
+    let standings = race_standings(
+        &config,
+        &race,
+        q_cars
+            .iter()
+            .map(|(e, _, progress, ..)| (e, progress.meters)),
+    );
+    let followed = config.camera_follow.or(config.hid_car);
+    let leader = standings.first().cloned();
+    for (mut row, mut color, children) in q_rows.iter_mut() {
+        let standing = standings.get(row.index);
+        row.car = standing.map(|s| s.entity);
+        let line = match standing.and_then(|s| q_cars.get(s.entity).ok().map(|c| (s, c))) {
+            Some((standing, (e, name, progress, stats, termination))) => {
+                let gap = match (&leader, standing.finished_at) {
+                    (Some(leader), _) if leader.entity == e => "-".to_string(),
+                    (Some(leader), Some(t)) => {
+                        format!("+{:.2}s", t - leader.finished_at.unwrap_or(t))
+                    }
+                    (Some(leader), None) => format!("+{:.0}m", leader.meters - progress.meters),
+                    (None, _) => "-".to_string(),
+                };
+                let status = match (standing.finished_at, termination.and_then(|t| t.done)) {
+                    (Some(_), _) => "finished",
+                    (None, Some(t)) if t.is_crash() => "crashed",
+                    (None, Some(_)) => "stopped",
+                    (None, None) => "running",
+                };
+                *color = UiColor(match Some(e) == followed {
+                    true => Color::rgba(1., 1., 1., 0.15),
+                    false => Color::NONE,
+                });
+                format!(
+                    "{:<2} {:<8} {:>3} {:>7} {:>7} {:>7} {}",
+                    row.index + 1,
+                    name.as_str().trim_start_matches("Car "),
+                    standing.laps,
+                    gap,
+                    lap_time(stats.lap_times.last().cloned()),
+                    lap_time(stats.best_lap()),
+                    status
+                )
+            }
+            None => "".to_string(),
+        };
+        for &child in children.iter() {
+            if let Ok(mut text) = q_text.get_mut(child) {
+                text.sections[0].value = line.clone();
+            }
+        }
     }
-    let mut text = q_leaderboard.single_mut();
-    text.sections[1].value = text_string;
 }
+
+pub fn dash_leaderboard_click_system(
+    mut config: ResMut<Config>,
+    q_rows: Query<(&Interaction, &LeaderboardRow), Changed<Interaction>>,
+) {
+    for (interaction, row) in q_rows.iter() {
+        if *interaction == Interaction::Clicked {
+            if let Some(car) = row.car {
+                config.follow_mode = FollowMode::Car;
+                config.camera_follow = Some(car);
+            }
+        }
+    }
+}
+
 pub fn dash_fps_system(diagnostics: Res<Diagnostics>, mut query: Query<&mut Text, With<FpsText>>) {
     for mut text in query.iter_mut() {
         if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
@@ -227,8 +365,16 @@ pub fn dash_fps_system(diagnostics: Res<Diagnostics>, mut query: Query<&mut Text
     }
 }
 
-pub fn dash_speed_start_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn dash_speed_start_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    asset_server: Res<AssetServer>,
+) {
     // commands.spawn_bundle(UiCameraBundle::default());
+    // split screen has a hud per player instead
+    if config.players > 1 {
+        return;
+    }
     let bold: Handle<Font> = asset_server.load("fonts/FiraSans-Bold.ttf");
     let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
     commands
@@ -380,6 +526,7 @@ pub fn dash_speed_start_system(mut commands: Commands, asset_server: Res<AssetSe
 }
 
 pub fn dash_speed_update_system(
+    config: Res<Config>,
     mut texts: ParamSet<(
         Query<&mut Text, With<MpsText>>,
         Query<&mut Text, With<KmphText>>,
@@ -389,6 +536,9 @@ pub fn dash_speed_update_system(
     mut cars: Query<(&Velocity, &Car, With<HID>)>,
     wheels: Query<(&Velocity, &ExternalForce), With<Wheel>>,
 ) {
+    if config.players > 1 {
+        return;
+    }
     let (velocity, car, _) = cars.single_mut();
 
     let mps = velocity.linvel.length();
@@ -4,12 +4,19 @@ use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use std::f32::consts::PI;
 
+// contact patch speed against the ground, zero when the wheel rolls without slipping
+pub fn wheel_slip(angvel: Vec3, linvel: Vec3, radius: f32) -> f32 {
+    let radius_vel = angvel * radius;
+    let velocity_slip = (radius_vel[0] - linvel[2], radius_vel[2] + linvel[0]);
+    (velocity_slip.0.powi(2) + velocity_slip.1.powi(2)).sqrt()
+}
+
 pub fn esp_system(
-    query: Query<(Entity, &Car, &Velocity, &Transform), Changed<Car>>,
+    query: Query<(Entity, &Car, &Velocity, &Transform), (Changed<Car>, Without<RemoteCar>)>,
     mut front: Query<(&mut MultibodyJoint, With<WheelFront>)>,
     mut wheel_set: ParamSet<(
-        Query<(&Wheel, &mut ExternalForce, &Transform, &Velocity), With<WheelFront>>,
-        Query<(&Wheel, &mut ExternalForce, &Transform, &Velocity), With<WheelBack>>,
+        Query<(&mut Wheel, &mut ExternalForce, &Transform, &Velocity), With<WheelFront>>,
+        Query<(&mut Wheel, &mut ExternalForce, &Transform, &Velocity), With<WheelBack>>,
     )>,
     // mut lines: ResMut<DebugLines>,
     // config: Res<Config>,
@@ -59,20 +66,19 @@ pub fn esp_system(
         let torque_vec = Vec3::new(0., torque, 0.);
         let steering_torque_vec = quat.mul_vec3(torque_vec);
 
-        for (_i, wheel_entity) in car.wheels.iter().enumerate() {
+        for wheel_entity in car.wheels.iter() {
             let mut q_front_wheels = wheel_set.p0();
             let wheel_result = q_front_wheels.get_mut(*wheel_entity);
-            if let Ok((wheel, mut f, transform, v)) = wheel_result {
-                let radius_vel = v.angvel * wheel.radius;
-                let velocity_slip = (radius_vel[0] - v.linvel[2], radius_vel[2] + v.linvel[0]);
-                let slip_sq = (velocity_slip.0.powi(2) + velocity_slip.1.powi(2)).sqrt();
+            if let Ok((mut wheel, mut f, transform, v)) = wheel_result {
+                let slip_sq = wheel_slip(v.angvel, v.linvel, wheel.radius);
+                wheel.slip = slip_sq;
                 let max_slip = 0.5;
                 let slip_sq_x: f32 = match slip_sq / max_slip {
                     x if x >= 1. => 0.,
                     x => 1. - x,
                 };
                 let total_torque = steering_torque_vec * slip_sq_x * torque_speed_x;
-                f.torque = (transform.rotation.mul_vec3(total_torque)).into();
+                f.torque = transform.rotation.mul_vec3(total_torque);
 
                 // if config.show_rays {
                 //     let start = transform.translation + Vec3::Y * 0.5;
@@ -81,17 +87,16 @@ pub fn esp_system(
                 // }
             }
 
-            if let Ok((wheel, mut f, transform, v)) = wheel_set.p1().get_mut(*wheel_entity) {
-                let radius_vel = v.angvel * wheel.radius;
-                let velocity_slip = (radius_vel[0] - v.linvel[2], radius_vel[2] + v.linvel[0]);
-                let slip_sq = (velocity_slip.0.powi(2) + velocity_slip.1.powi(2)).sqrt();
+            if let Ok((mut wheel, mut f, transform, v)) = wheel_set.p1().get_mut(*wheel_entity) {
+                let slip_sq = wheel_slip(v.angvel, v.linvel, wheel.radius);
+                wheel.slip = slip_sq;
                 let max_slip = 0.3;
                 let slip_sq_x: f32 = match slip_sq / max_slip {
                     x if x >= 1. => 0.,
                     x => 1. - x,
                 };
                 let total_torque = torque_vec * slip_sq_x * torque_speed_x;
-                f.torque = (transform.rotation.mul_vec3(total_torque)).into();
+                f.torque = transform.rotation.mul_vec3(total_torque);
 
                 // if config.show_rays {
                 //     let start = transform.translation + Vec3::Y * 0.5;
@@ -106,3 +111,26 @@ pub fn esp_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_rolling_wheel_does_not_slip() {
+        // 10 m/s along z on a 0.4 m wheel turning about x
+        let slip = wheel_slip(Vec3::X * 25., Vec3::Z * 10., 0.4);
+        assert!(slip.abs() < 1e-5);
+        assert_eq!(wheel_slip(Vec3::ZERO, Vec3::ZERO, 0.4), 0.);
+    }
+
+    #[test]
+    fn a_locked_or_spinning_wheel_slips_by_the_speed_difference() {
+        // locked under braking
+        assert_eq!(wheel_slip(Vec3::ZERO, Vec3::Z * 10., 0.4), 10.);
+        // spinning at standstill
+        assert_eq!(wheel_slip(Vec3::X * 10., Vec3::ZERO, 0.5), 5.);
+        // sliding sideways
+        assert_eq!(wheel_slip(Vec3::ZERO, Vec3::new(3., 0., 4.), 0.4), 5.);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::{
+    brain::CarBrain, car::Car, config::Config, contact::CarContacts, progress::CarProgress,
+    race::laps,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, path::Path};
+
+#[derive(Component, Debug, Default, Clone)]
+pub struct CarStats {
+    pub seconds: f32,
+    pub meters: f32,
+    pub laps: usize,
+    pub lap_started_at: f32,
+    pub lap_times: Vec<f32>,
+    pub off_track_seconds: f32,
+    pub collisions: usize,
+    pub collision_force: f32,
+    pub input_change: f32,
+    pub energy: f32,
+    pub last_input: Vec3,
+}
+
+impl CarStats {
+    pub fn best_lap(&self) -> Option<f32> {
+        self.lap_times.iter().cloned().reduce(f32::min)
+    }
+}
+
+pub trait FitnessTerm: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn value(&self, stats: &CarStats, config: &Config) -> f32;
+}
+
+pub struct Distance;
+impl FitnessTerm for Distance {
+    fn name(&self) -> &'static str {
+        "distance"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        stats.meters
+    }
+}
+
+// share of the lap driven, comparable across tracks of different length
+pub struct Progress;
+impl FitnessTerm for Progress {
+    fn name(&self) -> &'static str {
+        "progress"
+    }
+    fn value(&self, stats: &CarStats, config: &Config) -> f32 {
+        match config.meters_total > 0. {
+            true => stats.meters / config.meters_total,
+            false => 0.,
+        }
+    }
+}
+
+pub struct AverageSpeed;
+impl FitnessTerm for AverageSpeed {
+    fn name(&self) -> &'static str {
+        "avg speed"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        match stats.seconds > 0. {
+            true => stats.meters / stats.seconds,
+            false => 0.,
+        }
+    }
+}
+
+pub struct LapTime;
+impl FitnessTerm for LapTime {
+    fn name(&self) -> &'static str {
+        "lap time"
+    }
+    fn value(&self, stats: &CarStats, config: &Config) -> f32 {
+        match stats.best_lap() {
+            Some(lap) if lap > 0. => config.meters_total / lap,
+            _ => 0.,
+        }
+    }
+}
+
+pub struct OffTrack;
+impl FitnessTerm for OffTrack {
+    fn name(&self) -> &'static str {
+        "off track"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        stats.off_track_seconds
+    }
+}
+
+pub struct Collisions;
+impl FitnessTerm for Collisions {
+    fn name(&self) -> &'static str {
+        "collisions"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        stats.collisions as f32 + stats.collision_force / 10000.
+    }
+}
+
+pub struct Smoothness;
+impl FitnessTerm for Smoothness {
+    fn name(&self) -> &'static str {
+        "input change"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        stats.input_change
+    }
+}
+
+pub struct Energy;
+impl FitnessTerm for Energy {
+    fn name(&self) -> &'static str {
+        "energy"
+    }
+    fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+        stats.energy
+    }
+}
+
+// a weights file only needs the terms it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FitnessWeights {
+    pub distance: f32,
+    pub progress: f32,
+    pub average_speed: f32,
+    pub lap_time: f32,
+    pub off_track: f32,
+    pub collisions: f32,
+    pub smoothness: f32,
+    pub energy: f32,
+}
+
+impl FitnessWeights {
+    pub fn load(path: &Path) -> Result<FitnessWeights, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("unable to open weights {}: {}", path.display(), e))?;
+        serde_json::from_reader(file)
+            .map_err(|e| format!("unable to parse weights {}: {}", path.display(), e))
+    }
+}
+
+impl Default for FitnessWeights {
+    fn default() -> Self {
+        Self {
+            distance: 1.,
+            progress: 0.,
+            average_speed: 0.,
+            lap_time: 0.,
+            off_track: -1.,
+            collisions: -5.,
+            smoothness: 0.,
+            energy: 0.,
+        }
+    }
+}
+
+pub struct WeightedTerm {
+    pub term: Box<dyn FitnessTerm>,
+    pub weight: f32,
+}
+
+pub struct Fitness {
+    pub weights: FitnessWeights,
+    pub terms: Vec<WeightedTerm>,
+}
+
+impl Default for Fitness {
+    fn default() -> Self {
+        Fitness::new(FitnessWeights::default())
+    }
+}
+
+impl Fitness {
+    pub fn new(weights: FitnessWeights) -> Self {
+        let terms: Vec<(Box<dyn FitnessTerm>, f32)> = vec![
+            (Box::new(Distance), weights.distance),
+            (Box::new(Progress), weights.progress),
+            (Box::new(AverageSpeed), weights.average_speed),
+            (Box::new(LapTime), weights.lap_time),
+            (Box::new(OffTrack), weights.off_track),
+            (Box::new(Collisions), weights.collisions),
+            (Box::new(Smoothness), weights.smoothness),
+            (Box::new(Energy), weights.energy),
+        ];
+        Self {
+            weights,
+            terms: terms
+                .into_iter()
+                .filter(|(_, weight)| *weight != 0.)
+                .map(|(term, weight)| WeightedTerm { term, weight })
+                .collect(),
+        }
+    }
+    pub fn with_term(mut self, term: Box<dyn FitnessTerm>, weight: f32) -> Self {
+        self.terms.push(WeightedTerm { term, weight });
+        self
+    }
+    pub fn evaluate(&self, stats: &CarStats, config: &Config) -> CarFitness {
+        let breakdown: Vec<(&'static str, f32)> = self
+            .terms
+            .iter()
+            .map(|t| (t.term.name(), t.weight * t.term.value(stats, config)))
+            .collect();
+        CarFitness {
+            total: breakdown.iter().map(|(_, v)| v).sum(),
+            breakdown,
+        }
+    }
+}
+
+#[derive(Component, Debug, Default, Clone)]
+pub struct CarFitness {
+    pub total: f32,
+    pub breakdown: Vec<(&'static str, f32)>,
+}
+
+#[derive(Component)]
+pub struct FitnessText;
+
+pub fn car_stats_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut q_cars: Query<(
+        &Car,
+        &Velocity,
+        &CarProgress,
+        &CarContacts,
+        &mut CarStats,
+        Option<&CarBrain>,
+    )>,
+) {
+    let dt = time.delta_seconds();
+    for (car, velocity, progress, contacts, mut stats, brain) in q_cars.iter_mut() {
+        // players drive without a brain, from the start of the race
+        let driving = match brain {
+            Some(_) => car.use_brain,
+            None => config.reset_pause_until == 0.,
+        };
+        if !driving {
+            continue;
+        }
+        stats.meters = progress.meters;
+        stats.collisions = contacts.count();
+        stats.collision_force = contacts.force;
+        stats.seconds += dt;
+        let input = Vec3::new(car.gas, car.brake, car.steering);
+        let change = (input - stats.last_input).abs();
+        stats.input_change += change.x + change.y + change.z;
+        stats.last_input = input;
+        stats.energy += car.gas * velocity.linvel.length() * dt;
+        if progress.offset > config.road_half_width {
+            stats.off_track_seconds += dt;
+        }
+        let car_laps = laps(&config, progress.meters);
+        if car_laps > stats.laps {
+            let lap_time = stats.seconds - stats.lap_started_at;
+            stats.lap_times.push(lap_time);
+            stats.lap_started_at = stats.seconds;
+            stats.laps = car_laps;
+        }
+    }
+}
+
+pub fn car_fitness_system(
+    config: Res<Config>,
+    fitness: Res<Fitness>,
+    mut q_cars: Query<(&CarStats, &mut CarFitness)>,
+) {
+    for (stats, mut car_fitness) in q_cars.iter_mut() {
+        *car_fitness = fitness.evaluate(stats, &config);
+    }
+}
+
+pub fn fitness_start_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let bold: Handle<Font> = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(22.0),
+                    left: Val::Px(2.0),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "fitness: ".to_string(),
+                        style: TextStyle {
+                            font: bold.clone(),
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                        },
+                    },
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle {
+                            font: medium.clone(),
+                            font_size: 16.0,
+                            color: Color::GOLD,
+                        },
+                    },
+                ],
+                ..default()
+            },
+            ..default()
+        })
+        .insert(FitnessText);
+}
+
+pub fn dash_fitness_system(
+    config: Res<Config>,
+    q_cars: Query<(&Name, &CarFitness)>,
+    mut q_text: Query<&mut Text, With<FitnessText>>,
+) {
+    let e = match config.camera_follow.or(config.hid_car) {
+        Some(e) => e,
+        None => return,
+    };
+    if let Ok((name, car_fitness)) = q_cars.get(e) {
+        let mut text_string = format!("{:.1} {}\n", car_fitness.total, name);
+        for (term, value) in car_fitness.breakdown.iter() {
+            text_string = text_string + &format!("{}: {:.1}\n", term, value);
+        }
+        q_text.single_mut().sections[1].value = text_string;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> CarStats {
+        CarStats {
+            seconds: 20.,
+            meters: 400.,
+            lap_times: vec![90., 80.],
+            off_track_seconds: 3.,
+            collisions: 2,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn evaluate_sums_the_weighted_terms() {
+        let config = Config {
+            meters_total: 4000.,
+            ..default()
+        };
+        let fitness = Fitness::new(FitnessWeights {
+            progress: 10.,
+            average_speed: 2.,
+            lap_time: 1.,
+            ..default()
+        });
+        let car_fitness = fitness.evaluate(&stats(), &config);
+        let names: Vec<&str> = car_fitness.breakdown.iter().map(|(n, _)| *n).collect();
+        // zero weights are left out
+        assert_eq!(
+            names,
+            [
+                "distance",
+                "progress",
+                "avg speed",
+                "lap time",
+                "off track",
+                "collisions"
+            ]
+        );
+        let values: Vec<f32> = car_fitness.breakdown.iter().map(|(_, v)| *v).collect();
+        assert_eq!(values, [400., 1., 40., 50., -3., -10.]);
+        assert_eq!(car_fitness.total, 478.);
+    }
+
+    #[test]
+    fn an_added_term_is_weighted_like_the_others() {
+        struct Laps;
+        impl FitnessTerm for Laps {
+            fn name(&self) -> &'static str {
+                "laps"
+            }
+            fn value(&self, stats: &CarStats, _config: &Config) -> f32 {
+                stats.lap_times.len() as f32
+            }
+        }
+        let fitness = Fitness::new(FitnessWeights {
+            distance: 0.,
+            off_track: 0.,
+            collisions: 0.,
+            ..default()
+        })
+        .with_term(Box::new(Laps), 3.);
+        let car_fitness = fitness.evaluate(&stats(), &Config::default());
+        assert_eq!(car_fitness.breakdown, vec![("laps", 6.)]);
+        assert_eq!(car_fitness.total, 6.);
+    }
+
+    #[test]
+    fn a_weights_file_keeps_the_defaults_it_leaves_out() {
+        let weights: FitnessWeights = serde_json::from_str(r#"{"progress": 100}"#).unwrap();
+        let defaults = FitnessWeights::default();
+        assert_eq!(weights.progress, 100.);
+        assert_eq!(weights.distance, defaults.distance);
+        assert_eq!(weights.off_track, defaults.off_track);
+        assert_eq!(weights.collisions, defaults.collisions);
+        assert_eq!(weights.energy, defaults.energy);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    config::Config,
+    contact::CarContacts,
+    curriculum::{active_track_name, Curriculum},
+    progress::*,
+    termination::*,
+    trails::RibbonBuffers,
+    trainer::GenerationEvent,
+};
+use bevy::{prelude::*, render::view::NoFrustumCulling, utils::HashMap};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const BIN_METERS: f32 = 10.;
+// above the road and the trails
+const HEATMAP_LIFT: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeatBin {
+    pub crashes: usize,
+    pub walls: usize,
+    pub stalls: usize,
+}
+
+impl HeatBin {
+    // crashes weigh the most, wall scrapes the least
+    pub fn heat(&self) -> f32 {
+        3. * self.crashes as f32 + self.walls as f32 + 2. * self.stalls as f32
+    }
+}
+
+// failures binned by meters along the polyline, kept across generations
+pub struct Heatmap {
+    pub bin_meters: f32,
+    pub bins: Vec<HeatBin>,
+    // track variant the bins were counted on
+    pub track_name: Option<String>,
+    pub visible: bool,
+    pub changed: bool,
+    pub path: PathBuf,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            bin_meters: BIN_METERS,
+            bins: vec![],
+            track_name: None,
+            visible: false,
+            changed: false,
+            path: Path::new("metrics").join("heatmap.csv"),
+        }
+    }
+}
+
+impl Heatmap {
+    fn bin(&mut self, track_name: &str, config: &Config, meters: f32) -> Option<&mut HeatBin> {
+        if config.meters_total <= 0. {
+            return None;
+        }
+        // the curriculum changed the track, old positions mean nothing on it
+        if self.track_name.as_deref() != Some(track_name) {
+            self.track_name = Some(track_name.to_string());
+            let n = (config.meters_total / self.bin_meters).ceil() as usize;
+            self.bins = vec![HeatBin::default(); n.max(1)];
+        }
+        let along = (meters + config.meters_shift).rem_euclid(config.meters_total);
+        let i = ((along / self.bin_meters) as usize).min(self.bins.len() - 1);
+        self.changed = true;
+        self.bins.get_mut(i)
+    }
+
+    pub fn export(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(&self.path)?;
+        writeln!(file, "meters,crashes,walls,stalls")?;
+        for (i, bin) in self.bins.iter().enumerate() {
+            writeln!(
+                file,
+                "{},{},{},{}",
+                i as f32 * self.bin_meters,
+                bin.crashes,
+                bin.walls,
+                bin.stalls
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// what was already counted, so a car stuck in one state is counted once
+#[derive(Default)]
+pub struct HeatmapSeen {
+    pub terminated: HashMap<Entity, bool>,
+    pub walls: HashMap<Entity, usize>,
+}
+
+#[derive(Component)]
+pub struct HeatmapMesh;
+
+pub fn heatmap_record_system(
+    config: Res<Config>,
+    curriculum: Option<Res<Curriculum>>,
+    mut heatmap: ResMut<Heatmap>,
+    mut seen: Local<HeatmapSeen>,
+    q_cars: Query<(Entity, &CarProgress, &CarContacts, &CarTermination)>,
+) {
+    let track_name = active_track_name(&config, curriculum.as_deref());
+    for (e, progress, contacts, termination) in q_cars.iter() {
+        let done = termination.done;
+        if seen.terminated.insert(e, done.is_some()) != Some(true) {
+            if let Some(done) = done {
+                if let Some(bin) = heatmap.bin(&track_name, &config, progress.meters) {
+                    match done {
+                        Termination::NoProgress => bin.stalls += 1,
+                        t if t.is_crash() => bin.crashes += 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        // contacts are cleared when the cars reset
+        let walls = seen.walls.insert(e, contacts.walls).unwrap_or(0);
+        if contacts.walls > walls {
+            if let Some(bin) = heatmap.bin(&track_name, &config, progress.meters) {
+                bin.walls += contacts.walls - walls;
+            }
+        }
+    }
+}
+
+pub fn heatmap_export_system(
+    keys: Res<Input<KeyCode>>,
+    mut heatmap: ResMut<Heatmap>,
+    mut e_generation: EventReader<GenerationEvent>,
+) {
+    let mut export = e_generation.iter().count() > 0;
+    if keys.just_pressed(KeyCode::H) {
+        heatmap.visible = !heatmap.visible;
+        heatmap.changed = true;
+        export |= heatmap.visible;
+    }
+    if export && !heatmap.bins.is_empty() {
+        match heatmap.export() {
+            Ok(()) => println!("heatmap saved to {}", heatmap.path.display()),
+            Err(e) => println!("unable to write {}: {}", heatmap.path.display(), e),
+        }
+    }
+}
+
+pub fn heatmap_start_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(RibbonBuffers::default().into_mesh()),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                cull_mode: None,
+                ..default()
+            }),
+            transform: Transform::from_translation(Vec3::Y * HEATMAP_LIFT),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(Name::new("Heatmap"))
+        .insert(HeatmapMesh)
+        .insert(NoFrustumCulling);
+}
+
+// yellow to red, transparent where nothing happened
+fn heat_color(t: f32) -> Color {
+    Color::rgba(1., 1. - t, 0., 0.25 + 0.5 * t)
+}
+
+pub fn heatmap_system(
+    config: Res<Config>,
+    mut heatmap: ResMut<Heatmap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut q_mesh: Query<(&Handle<Mesh>, &mut Visibility), With<HeatmapMesh>>,
+) {
+    if !heatmap.changed {
+        return;
+    }
+    let (handle, mut visibility) = q_mesh.single_mut();
+    visibility.is_visible = heatmap.visible;
+    if !heatmap.visible {
+        return;
+    }
+    heatmap.changed = false;
+    let max = heatmap.bins.iter().map(|b| b.heat()).fold(0., f32::max);
+    let mut buffers = RibbonBuffers::default();
+    for (i, bin) in heatmap.bins.iter().enumerate() {
+        if bin.heat() <= 0. {
+            continue;
+        }
+        let from = i as f32 * heatmap.bin_meters;
+        let to = from + heatmap.bin_meters;
+        let (a, da) = match polyline_point(&config, from) {
+            Some(p) => p,
+            None => continue,
+        };
+        let (b, db) = match polyline_point(&config, to) {
+            Some(p) => p,
+            None => continue,
+        };
+        let side_a = da.cross(Vec3::Y).normalize_or_zero() * config.road_half_width;
+        let side_b = db.cross(Vec3::Y).normalize_or_zero() * config.road_half_width;
+        let color = heat_color(bin.heat() / max).as_rgba_f32();
+        for p in [
+            a - side_a,
+            a + side_a,
+            b + side_b,
+            a - side_a,
+            b + side_b,
+            b - side_b,
+        ] {
+            buffers.positions.push(p.to_array());
+            buffers.colors.push(color);
+        }
+    }
+    if let Some(mesh) = meshes.get_mut(handle) {
+        *mesh = buffers.into_mesh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(meters_total: f32, meters_shift: f32) -> Config {
+        Config {
+            meters_total,
+            meters_shift,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn bins_wrap_around_the_lap() {
+        let mut heatmap = Heatmap::default();
+        let config = config(95., 10.);
+        heatmap.bin("a", &config, 3.).unwrap().crashes += 1;
+        heatmap.bin("a", &config, 90.).unwrap().walls += 1;
+        heatmap.bin("a", &config, -8.).unwrap().stalls += 1;
+        assert_eq!(heatmap.bins.len(), 10);
+        assert_eq!(heatmap.bins[1].crashes, 1);
+        // 90 + 10 wraps to 5
+        assert_eq!(heatmap.bins[0].walls, 1);
+        assert_eq!(heatmap.bins[0].stalls, 1);
+        assert!(heatmap.changed);
+    }
+
+    #[test]
+    fn a_new_track_starts_empty() {
+        let mut heatmap = Heatmap::default();
+        heatmap.bin("a", &config(100., 0.), 55.).unwrap().crashes += 1;
+        assert_eq!(heatmap.bins[5].crashes, 1);
+        heatmap.bin("b", &config(200., 0.), 0.);
+        assert_eq!(heatmap.bins.len(), 20);
+        assert_eq!(heatmap.bins.iter().map(|b| b.crashes).sum::<usize>(), 0);
+        assert!(heatmap.bin("b", &config(0., 0.), 10.).is_none());
+    }
+
+    #[test]
+    fn switching_variants_of_the_same_length_clears_the_bins() {
+        let mut heatmap = Heatmap::default();
+        let config = config(100., 0.);
+        heatmap.bin("a", &config, 55.).unwrap().crashes += 1;
+        heatmap.bin("a", &config, 55.).unwrap().walls += 1;
+        assert_eq!(heatmap.bins[5].heat(), 4.);
+        // mirrored, same meters_total
+        heatmap.bin("a-mirrored", &config, 15.).unwrap().stalls += 1;
+        assert_eq!(heatmap.track_name.as_deref(), Some("a-mirrored"));
+        assert_eq!(heatmap.bins[5].heat(), 0.);
+        assert_eq!(heatmap.bins[1].stalls, 1);
+        // and back again starts over too
+        heatmap.bin("a", &config, 55.);
+        assert_eq!(heatmap.bins.iter().map(|b| b.heat()).sum::<f32>(), 0.);
+    }
+}
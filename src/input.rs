@@ -1,35 +1,195 @@
-use crate::car::*;
+use crate::{car::*, gamepad::GamepadLobby};
 use bevy::prelude::*;
 
-pub fn arrow_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut cars: Query<(&mut Car, &Transform, With<HID>)>,
-) {
-    let (mut car, _transform, _car) = cars.single_mut();
-    if keyboard_input.pressed(KeyCode::Up) {
+// up, down, left, right for each player sharing the keyboard
+pub const KEY_ZONES: [[KeyCode; 4]; 4] = [
+    [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right],
+    [KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D],
+    // the block above the arrows, letters are taken by the hotkeys
+    [
+        KeyCode::Home,
+        KeyCode::End,
+        KeyCode::Delete,
+        KeyCode::PageDown,
+    ],
+    [
+        KeyCode::Numpad8,
+        KeyCode::Numpad5,
+        KeyCode::Numpad4,
+        KeyCode::Numpad6,
+    ],
+];
+pub const MAX_PLAYERS: usize = KEY_ZONES.len();
+const STICK_DEAD_ZONE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerInput {
+    Keyboard([KeyCode; 4]),
+    Gamepad(Gamepad),
+}
+
+// a human driven car, player 0 is also the HID car
+#[derive(Component, Debug)]
+pub struct Player {
+    pub index: usize,
+    pub input: PlayerInput,
+}
+
+impl Player {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            input: PlayerInput::Keyboard(KEY_ZONES[index % MAX_PLAYERS]),
+        }
+    }
+}
+
+// the last players take the connected gamepads, the first ones keep their keyboard zone
+pub fn player_inputs(players: usize, lobby: &GamepadLobby) -> Vec<PlayerInput> {
+    let mut gamepads: Vec<Gamepad> = lobby.gamepads.iter().cloned().collect();
+    gamepads.sort_by_key(|g| g.id);
+    let first_gamepad = players.saturating_sub(gamepads.len());
+    (0..players)
+        .map(|i| match i >= first_gamepad {
+            true => PlayerInput::Gamepad(gamepads[i - first_gamepad]),
+            false => PlayerInput::Keyboard(KEY_ZONES[i % MAX_PLAYERS]),
+        })
+        .collect()
+}
+
+pub fn player_assign_system(lobby: Res<GamepadLobby>, mut q_players: Query<&mut Player>) {
+    if !lobby.is_changed() {
+        return;
+    }
+    let inputs = player_inputs(q_players.iter().count(), &lobby);
+    for mut player in q_players.iter_mut() {
+        if let Some(input) = inputs.get(player.index) {
+            if player.input != *input {
+                println!("player {} uses {:?}", player.index + 1, input);
+                player.input = *input;
+            }
+        }
+    }
+}
+
+fn keyboard_drive(car: &mut Car, keyboard_input: &Input<KeyCode>, zone: &[KeyCode; 4]) {
+    let [up, down, left, right] = *zone;
+    if keyboard_input.pressed(up) {
         car.gas = 1.;
     }
-    if keyboard_input.just_released(KeyCode::Up) {
+    if keyboard_input.just_released(up) {
         car.gas = 0.;
     }
 
-    if keyboard_input.pressed(KeyCode::Down) {
+    if keyboard_input.pressed(down) {
         car.brake = 1.;
     }
-    if keyboard_input.just_released(KeyCode::Down) {
+    if keyboard_input.just_released(down) {
         car.brake = 0.;
     }
 
-    if keyboard_input.just_pressed(KeyCode::Left) {
+    if keyboard_input.just_pressed(left) {
         car.steering = -1.;
     }
-    if keyboard_input.just_pressed(KeyCode::Right) {
+    if keyboard_input.just_pressed(right) {
         car.steering = 1.;
     }
-    if keyboard_input.just_released(KeyCode::Left) {
+    if keyboard_input.just_released(left) {
         car.steering = 0.;
     }
-    if keyboard_input.just_released(KeyCode::Right) {
+    if keyboard_input.just_released(right) {
         car.steering = 0.;
     }
 }
+
+fn gamepad_drive(
+    car: &mut Car,
+    gamepad: Gamepad,
+    button_axes: &Axis<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) {
+    let trigger = |button_type| {
+        button_axes
+            .get(GamepadButton::new(gamepad, button_type))
+            .unwrap_or(0.)
+    };
+    car.gas = trigger(GamepadButtonType::RightTrigger2);
+    car.brake = trigger(GamepadButtonType::LeftTrigger2);
+    let stick = axes
+        .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+        .unwrap_or(0.);
+    car.steering = match stick.abs() > STICK_DEAD_ZONE {
+        true => stick,
+        false => 0.,
+    };
+}
+
+pub fn player_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut cars: Query<(&mut Car, &Player)>,
+) {
+    let players = cars.iter().count();
+    for (mut car, player) in cars.iter_mut() {
+        match player.input {
+            PlayerInput::Keyboard(ref zone) => keyboard_drive(&mut car, &keyboard_input, zone),
+            // a lone player keeps the arrows next to the gamepad
+            PlayerInput::Gamepad(_)
+                if players == 1
+                    && (keyboard_input.any_pressed(KEY_ZONES[0])
+                        || keyboard_input.any_just_released(KEY_ZONES[0])) =>
+            {
+                keyboard_drive(&mut car, &keyboard_input, &KEY_ZONES[0])
+            }
+            PlayerInput::Gamepad(gamepad) => gamepad_drive(&mut car, gamepad, &button_axes, &axes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lobby(ids: &[usize]) -> GamepadLobby {
+        GamepadLobby {
+            gamepads: ids.iter().map(|id| Gamepad::new(*id)).collect(),
+        }
+    }
+
+    #[test]
+    fn gamepads_go_to_the_last_players_in_id_order() {
+        assert_eq!(
+            player_inputs(3, &lobby(&[7, 2])),
+            vec![
+                PlayerInput::Keyboard(KEY_ZONES[0]),
+                PlayerInput::Gamepad(Gamepad::new(2)),
+                PlayerInput::Gamepad(Gamepad::new(7)),
+            ]
+        );
+        assert_eq!(
+            player_inputs(2, &lobby(&[])),
+            vec![
+                PlayerInput::Keyboard(KEY_ZONES[0]),
+                PlayerInput::Keyboard(KEY_ZONES[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_gamepads_are_left_over() {
+        assert_eq!(
+            player_inputs(1, &lobby(&[4, 1, 9])),
+            vec![PlayerInput::Gamepad(Gamepad::new(1))]
+        );
+    }
+
+    #[test]
+    fn key_zones_share_no_keys() {
+        let mut keys: Vec<KeyCode> = KEY_ZONES.iter().flatten().cloned().collect();
+        let n = keys.len();
+        keys.sort_by_key(|k| *k as u32);
+        keys.dedup();
+        assert_eq!(keys.len(), n);
+    }
+}
@@ -0,0 +1,40 @@
+// bevy systems take their resources and queries as arguments
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+pub mod brain;
+pub mod brain_file;
+pub mod brain_view;
+pub mod camera;
+pub mod car;
+pub mod chart;
+pub mod checkpoint;
+pub mod cli;
+pub mod config;
+pub mod contact;
+pub mod curriculum;
+pub mod dash;
+pub mod esp;
+pub mod fitness;
+pub mod gamepad;
+pub mod heatmap;
+pub mod input;
+pub mod library;
+pub mod light;
+pub mod mesh;
+pub mod metrics;
+pub mod minimap;
+pub mod net;
+pub mod noise;
+pub mod plain;
+pub mod players;
+pub mod progress;
+pub mod race;
+pub mod rays;
+pub mod rng;
+pub mod telemetry;
+pub mod termination;
+pub mod track;
+pub mod trails;
+pub mod trainer;
+pub mod util;
+pub mod worlds;
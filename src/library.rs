@@ -0,0 +1,378 @@
+use crate::{brain::*, brain_file::*, config::Config, trainer::*};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrainMetadata {
+    pub track: String,
+    pub topology: Vec<usize>,
+    pub sensors: SensorLayout,
+    pub fitness: f32,
+    pub generation: i32,
+    pub parents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub name: String,
+    pub created_at: u64,
+    pub metadata: BrainMetadata,
+    pub brain: CarBrain,
+}
+
+impl LibraryEntry {
+    // the metadata holds what a brain file keeps in its header
+    pub fn brain_file(&self) -> BrainFile {
+        BrainFile {
+            header: BrainHeader {
+                format_version: BRAIN_FORMAT_VERSION,
+                topology: self.metadata.topology.clone(),
+                activation: Activation::Step,
+                sensors: self.metadata.sensors.clone(),
+            },
+            brain: self.brain.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BrainCommand {
+    List,
+    Load(String),
+    Rename(String, String),
+    Delete(String),
+    Compare(String, String),
+    Convert(PathBuf, PathBuf),
+}
+
+pub struct Library {
+    pub dir: PathBuf,
+    pub hall_size: usize,
+    pub parents: Vec<String>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            dir: Path::new("brains").to_path_buf(),
+            hall_size: 10,
+            parents: vec![],
+        }
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl Library {
+    fn path(&self, name: &str) -> Result<PathBuf, String> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(format!("invalid brain name {:?}", name));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub fn load(&self, name: &str) -> Result<LibraryEntry, String> {
+        let path = self.path(name)?;
+        let file =
+            File::open(&path).map_err(|e| format!("unable to open brain {}: {}", name, e))?;
+        serde_json::from_reader(file).map_err(|e| format!("unable to parse brain {}: {}", name, e))
+    }
+
+    // checked like load_brain checks brain.json
+    pub fn load_checked(&self, name: &str, sensors: &SensorLayout) -> Result<LibraryEntry, String> {
+        let entry = self.load(name)?;
+        let path = self.path(name)?;
+        let file = entry.brain_file();
+        file.validate(&path)
+            .and_then(|_| file.check_sensors(&path, sensors))
+            .map_err(|e| e.to_string())?;
+        Ok(entry)
+    }
+
+    pub fn save(&self, entry: &LibraryEntry) -> Result<PathBuf, String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let path = self.path(&entry.name)?;
+        let serialized = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        fs::write(&path, serialized).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    pub fn entries(&self) -> Vec<LibraryEntry> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(_) => return vec![],
+        };
+        let mut entries: Vec<LibraryEntry> = dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|p| {
+                let name = p.file_stem()?.to_str()?.to_string();
+                match self.load(&name) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        entries.sort_by_key(|e| e.created_at);
+        entries
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        if self.path(to)?.exists() {
+            return Err(format!("brain {} already exists", to));
+        }
+        let mut entry = self.load(from)?;
+        entry.name = to.to_string();
+        self.save(&entry)?;
+        self.delete(from)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        fs::remove_file(self.path(name)?).map_err(|e| format!("unable to delete {}: {}", name, e))
+    }
+
+    // keeps the best hall_size entries, returns the name of the saved entry
+    pub fn hall_of_fame(&self, entry: LibraryEntry) -> Result<Option<String>, String> {
+        let mut entries = self.entries();
+        if entries.len() >= self.hall_size {
+            entries.sort_by(|a, b| {
+                b.metadata
+                    .fitness
+                    .partial_cmp(&a.metadata.fitness)
+                    .unwrap_or(Ordering::Equal)
+            });
+            let worst = &entries[self.hall_size - 1];
+            if entry.metadata.fitness <= worst.metadata.fitness {
+                return Ok(None);
+            }
+            for removed in entries[self.hall_size - 1..].iter() {
+                self.delete(&removed.name)?;
+            }
+        }
+        self.save(&entry)?;
+        Ok(Some(entry.name))
+    }
+}
+
+pub fn run_brain_command(
+    library: &Library,
+    command: &BrainCommand,
+    sensors: &SensorLayout,
+) -> Result<(), String> {
+    match command {
+        BrainCommand::List => {
+            for entry in library.entries() {
+                println!(
+                    "{:<32} fitness {:>8.1} gen {:>4} track {} topology {:?} parents {:?}",
+                    entry.name,
+                    entry.metadata.fitness,
+                    entry.metadata.generation,
+                    entry.metadata.track,
+                    entry.metadata.topology,
+                    entry.metadata.parents
+                );
+            }
+            Ok(())
+        }
+        BrainCommand::Load(name) => {
+            let entry = library.load_checked(name, sensors)?;
+            write_brain_file(Path::new("brain.json"), &entry.brain_file())
+                .map_err(|e| e.to_string())?;
+            println!("{} is the active brain in brain.json", name);
+            Ok(())
+        }
+        BrainCommand::Rename(from, to) => library.rename(from, to),
+        BrainCommand::Delete(name) => library.delete(name),
+        BrainCommand::Convert(from, to) => {
+            convert_brain(from, to, sensors).map_err(|e| e.to_string())
+        }
+        BrainCommand::Compare(a, b) => {
+            let a = library.load(a)?;
+            let b = library.load(b)?;
+            for entry in [&a, &b] {
+                println!(
+                    "{:<32} fitness {:>8.1} gen {:>4} track {} sensors {:?}",
+                    entry.name,
+                    entry.metadata.fitness,
+                    entry.metadata.generation,
+                    entry.metadata.track,
+                    entry.metadata.sensors
+                );
+            }
+            if a.metadata.topology != b.metadata.topology {
+                println!(
+                    "topology differs {:?} {:?}",
+                    a.metadata.topology, b.metadata.topology
+                );
+                return Ok(());
+            }
+            let distance: f32 = a
+                .brain
+                .levels
+                .iter()
+                .zip(b.brain.levels.iter())
+                .flat_map(|(la, lb)| {
+                    let weights = la.weights.iter().zip(lb.weights.iter());
+                    let biases = la.biases.iter().zip(lb.biases.iter());
+                    weights.chain(biases).map(|(wa, wb)| (wa - wb).powi(2))
+                })
+                .sum::<f32>()
+                .sqrt();
+            println!("weight distance {:.4}", distance);
+            Ok(())
+        }
+    }
+}
+
+pub fn library_start_brains(
+    library: &Library,
+    names: &[String],
+    sensors: &SensorLayout,
+) -> Result<StartBrains, String> {
+    let mut brains: Vec<CarBrain> = vec![];
+    for name in names.iter() {
+        let entry = library.load_checked(name, sensors)?;
+        println!(
+            "seeding from {} fitness {:.1}",
+            name, entry.metadata.fitness
+        );
+        brains.push(entry.brain);
+    }
+    Ok(StartBrains {
+        brains,
+        mutate: false,
+        ..default()
+    })
+}
+
+pub fn library_system(
+    config: Res<Config>,
+    trainer: Res<Trainer>,
+    mut library: ResMut<Library>,
+    mut e_generation: EventReader<GenerationEvent>,
+) {
+    for generation_e in e_generation.iter() {
+        let brain = match &trainer.best_brain {
+            Some(brain) => brain.clone(),
+            None => continue,
+        };
+        let fitness = generation_e
+            .results
+            .iter()
+            .map(|r| r.fitness)
+            .fold(f32::MIN, f32::max);
+        let generation = generation_e.generation - 1;
+        let created_at = unix_seconds();
+        let entry = LibraryEntry {
+            name: format!("gen-{:06}-{}", generation, created_at),
+            created_at,
+            metadata: BrainMetadata {
+                track: generation_e.track.clone(),
+                topology: brain.topology(),
+                sensors: config.sensor_layout(),
+                fitness,
+                generation,
+                parents: library.parents.clone(),
+            },
+            brain,
+        };
+        match library.hall_of_fame(entry) {
+            Ok(Some(name)) => {
+                println!("hall of fame {} fitness {:.1}", name, fitness);
+                library.parents = vec![name];
+            }
+            Ok(None) => {}
+            Err(e) => println!("unable to save brain: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SimRng;
+
+    fn library(name: &str, hall_size: usize) -> Library {
+        let dir = std::env::temp_dir().join(format!("car-sim-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        Library {
+            dir,
+            hall_size,
+            parents: vec![],
+        }
+    }
+
+    fn entry(name: &str, fitness: f32) -> LibraryEntry {
+        let sensors = Config::default().sensor_layout();
+        let brain = CarBrain::new(sensors.inputs(), &mut SimRng::new(1).rng);
+        LibraryEntry {
+            name: name.to_string(),
+            created_at: 0,
+            metadata: BrainMetadata {
+                track: "test".to_string(),
+                topology: brain.topology(),
+                sensors,
+                fitness,
+                generation: 0,
+                parents: vec![],
+            },
+            brain,
+        }
+    }
+
+    fn names(library: &Library) -> Vec<String> {
+        let mut names: Vec<String> = library.entries().into_iter().map(|e| e.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn names_cannot_leave_the_library_dir() {
+        let library = library("names", 10);
+        library.save(&entry("kept", 1.)).unwrap();
+        for name in ["../kept", "a/b", "a\\b", "..", ".hidden", ""] {
+            assert!(library.rename("kept", name).is_err(), "{:?}", name);
+            assert!(library.delete(name).is_err(), "{:?}", name);
+            assert!(library.save(&entry(name, 1.)).is_err(), "{:?}", name);
+        }
+        assert!(library.rename("../kept", "other").is_err());
+        assert_eq!(names(&library), ["kept"]);
+        library.rename("kept", "renamed").unwrap();
+        assert_eq!(names(&library), ["renamed"]);
+        fs::remove_dir_all(&library.dir).unwrap();
+    }
+
+    #[test]
+    fn hall_of_fame_keeps_the_best_hall_size() {
+        let library = library("hall", 3);
+        for (name, fitness) in [("a", 5.), ("b", 1.), ("c", 3.)] {
+            assert!(library
+                .hall_of_fame(entry(name, fitness))
+                .unwrap()
+                .is_some());
+        }
+        // not better than the worst kept
+        assert_eq!(library.hall_of_fame(entry("d", 1.)).unwrap(), None);
+        assert_eq!(names(&library), ["a", "b", "c"]);
+        assert_eq!(
+            library.hall_of_fame(entry("e", 4.)).unwrap(),
+            Some("e".to_string())
+        );
+        assert_eq!(names(&library), ["a", "c", "e"]);
+        fs::remove_dir_all(&library.dir).unwrap();
+    }
+}
@@ -1,42 +1,114 @@
-mod brain;
-mod camera;
-mod car;
-mod config;
-mod dash;
-mod esp;
-mod gamepad;
-mod input;
-mod light;
-mod mesh;
-mod plain;
-mod progress;
-mod track;
-mod trainer;
-mod util;
-
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
-// use bevy_prototype_debug_lines::DebugLinesPlugin;
 use bevy_rapier3d::prelude::*;
-
-use brain::*;
-use camera::*;
-use car::*;
-use config::*;
-use dash::*;
-use esp::*;
-use gamepad::*;
-use input::*;
-use light::*;
-use plain::*;
-use progress::*;
-use track::*;
-use trainer::*;
+use bevy_rapier_3d_car_sim::{
+    brain::*, brain_view::*, camera::*, car::*, chart::*, checkpoint::*, cli::*, config::*,
+    contact::*, curriculum::*, dash::*, esp::*, fitness::*, gamepad::*, heatmap::*, input::*,
+    library::*, light::*, metrics::*, minimap::*, net::*, noise::*, plain::*, players::*,
+    progress::*, race::*, rays::*, rng::*, telemetry::*, termination::*, track::*, trails::*,
+    trainer::*, worlds::*,
+};
 
 fn main() {
-    App::new()
-        .insert_resource(Msaa { samples: 4 })
-        .insert_resource(Config::default())
-        .insert_resource(Trainer::default())
+    let args = Args::parse();
+    let library = Library::default();
+    let mut config = Config {
+        players: args.players.max(1),
+        car_sensors: args.car_sensors,
+        // the server races, the grid has to match
+        race_mode: args.race || args.connect.is_some(),
+        ..default()
+    };
+    if let Some(seed) = args.seed {
+        config.seed = seed;
+    }
+    if let Some(command) = &args.brain_command {
+        run_brain_command(&library, command, &config.sensor_layout())
+            .unwrap_or_else(|e| exit_with(&e));
+        return;
+    }
+    let mut trainer = Trainer::default();
+    let mut rng = SimRng::new(config.seed);
+    let mut fitness = Fitness::default();
+    let mut noise = Noise::default();
+    let mut start_brains = StartBrains::default();
+    if !args.brains.is_empty() {
+        start_brains = library_start_brains(&library, &args.brains, &config.sensor_layout())
+            .unwrap_or_else(|e| exit_with(&e));
+    }
+    let mut metrics_log = MetricsLog::default();
+    if let Some(format) = args.metrics {
+        metrics_log.format = format;
+    }
+    let mut curriculum = args.curriculum.then(|| Curriculum::new(&config));
+    if let Some(path) = &args.resume {
+        let checkpoint = Checkpoint::load(path).unwrap_or_else(|e| exit_with(&e));
+        println!(
+            "resuming generation {} from {}",
+            checkpoint.generation,
+            path.display()
+        );
+        checkpoint
+            .restore(
+                config.brain_inputs(),
+                &mut trainer,
+                &mut rng,
+                &mut fitness,
+                &mut noise,
+                &mut start_brains,
+                curriculum.as_mut(),
+            )
+            .unwrap_or_else(|e| exit_with(&e));
+    }
+    if let Some(path) = &args.noise {
+        noise = Noise::load(path).unwrap_or_else(|e| exit_with(&e));
+    }
+    // lap share instead of meters so tracks of any length weigh the same
+    if curriculum.is_some() && args.resume.is_none() {
+        fitness = Fitness::new(FitnessWeights {
+            distance: 0.,
+            progress: 100.,
+            ..default()
+        });
+    }
+    if let Some(path) = &args.weights {
+        fitness = Fitness::new(FitnessWeights::load(path).unwrap_or_else(|e| exit_with(&e)));
+    }
+    let mut rules = TerminationRules::default();
+    if let Some(path) = &args.termination {
+        rules = TerminationRules::load(path).unwrap_or_else(|e| exit_with(&e));
+    }
+    let worlds = spawn_training_worlds(
+        args.worlds,
+        &config,
+        &trainer,
+        &fitness.weights,
+        &rules,
+        &noise,
+        &start_brains,
+        curriculum.as_ref(),
+    );
+    let mut app = App::new();
+    app.insert_resource(Msaa { samples: 4 })
+        .insert_resource(rng)
+        .insert_resource(config)
+        .insert_resource(noise)
+        .insert_resource(trainer)
+        .insert_resource(Race::default())
+        .insert_resource(fitness)
+        .insert_resource(start_brains)
+        .insert_resource(metrics_log)
+        .insert_resource(Library {
+            parents: args.brains.clone(),
+            ..library
+        })
+        .init_resource::<MetricsHistory>()
+        .init_resource::<BrainActivity>()
+        .init_resource::<RayLines>()
+        .init_resource::<Trails>()
+        .init_resource::<Heatmap>()
+        .init_resource::<Telemetry>()
+        .add_event::<GenerationEvent>()
+        .insert_resource(rules)
         .add_plugins(DefaultPlugins)
         // .insert_resource(bevy_atmosphere::AtmosphereMat::default())
         // .add_plugin(bevy_atmosphere::AtmospherePlugin {
@@ -46,7 +118,9 @@ fn main() {
         .add_startup_system(camera_start_system)
         .add_system(camera_controller_system)
         .add_system(camera_switch_system)
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_startup_system(split_screen_start_system)
+        .add_system(split_screen_viewport_system)
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         // .add_plugin(RapierDebugRenderPlugin {
         //     // | DebugRenderMode::COLLIDER_AABBS
@@ -58,7 +132,6 @@ fn main() {
         //     ..default()
         // })
         // .add_plugin(PolylinePlugin)
-        // .add_plugin(DebugLinesPlugin::with_depth_test(true))
         // .add_plugins(DefaultPickingPlugins)
         // .add_plugin(DebugCursorPickingPlugin)
         .init_resource::<GamepadLobby>()
@@ -69,36 +142,78 @@ fn main() {
         .add_startup_system(light_start_system)
         .add_startup_system(car_start_system)
         .add_startup_system(dash_speed_start_system)
+        .add_startup_system(player_hud_start_system)
         .add_startup_system(dash_fps_start_system)
+        .add_startup_system(dash_leaderboard_start_system)
+        // race_begin takes the brains off, cars must have spawned with theirs
+        .add_startup_system(race_start_system.after(car_start_system))
+        .add_startup_system(fitness_start_system)
+        .add_startup_system(learning_curve_start_system)
+        .add_startup_system(brain_view_start_system)
+        .add_startup_system(minimap_start_system)
+        .add_startup_system(rays_start_system)
+        .add_startup_system(trails_start_system.after(track_polyline_start_system))
+        .add_startup_system(heatmap_start_system)
+        .add_startup_system(telemetry_start_system)
         .add_system(esp_system)
         .add_system(car_brain_system)
         .add_system(trainer_system)
+        .add_system(checkpoint_system.after(trainer_system))
+        .add_system(metrics_system.after(trainer_system))
+        .add_system(library_system.after(trainer_system))
+        .add_system(learning_curve_system.after(metrics_system))
         .add_system(dash_fps_system)
         .add_system(dash_leaderboard_system)
+        .add_system(dash_leaderboard_click_system)
+        .add_system(minimap_system)
+        .add_system(minimap_click_system)
         .add_system(dash_speed_update_system)
+        .add_system(player_hud_system)
         // .add_system(gamepad_input_system)
-        .add_system(arrow_input_system)
+        .add_system(player_assign_system)
+        .add_system(player_input_system.after(player_assign_system))
         .add_system(reset_pos_system)
         .add_system(progress_system)
         .add_system(reset_spawn_key_system)
         .add_system(reset_force_system)
+        .add_system(race_system)
+        .add_system(car_collisions_key_system)
+        .add_system(car_contacts_system)
+        .add_system(car_stats_system)
+        .add_system(car_fitness_system)
+        .add_system(termination_system)
+        .add_system(dash_fitness_system)
+        .add_system(brain_view_system.after(car_brain_system))
+        .add_system(rays_key_system.before(car_brain_system))
+        .add_system(rays_system.after(car_brain_system))
+        .add_system(trails_key_system)
+        .add_system(trails_record_system.after(progress_system))
+        .add_system(trails_system.after(trails_record_system))
+        .add_system(
+            heatmap_record_system
+                .after(termination_system)
+                .before(trainer_system),
+        )
+        .add_system(heatmap_export_system.after(trainer_system))
+        .add_system(heatmap_system.after(heatmap_export_system))
+        .add_system(telemetry_record_system.after(esp_system))
+        .add_system(telemetry_click_system)
+        .add_system(telemetry_system.after(telemetry_record_system))
         .add_system_to_stage(CoreStage::PreUpdate, gamepad_stage_preupdate_system)
-        .add_system_to_stage(CoreStage::PostUpdate, display_events_system)
-        .run();
-}
-
-fn display_events_system(
-    // mut e_collision: EventReader<CollisionEvent>,
-    mut e_force: EventReader<ContactForceEvent>,
-) {
-    // for collision_e in e_collision.iter() {
-    //     println!("collision: {:?}", collision_e);
-    // }
-
-    for force_e in e_force.iter() {
-        println!(
-            "force: {:?} {:?}",
-            force_e.total_force, force_e.total_force_magnitude
-        );
+        .add_system_to_stage(CoreStage::PostUpdate, car_contact_forces_system);
+    if let Some(worlds) = worlds {
+        app.insert_resource(worlds);
+    }
+    if let Some(curriculum) = curriculum {
+        app.insert_resource(curriculum)
+            .add_system(curriculum_track_system.after(trainer_system));
+    }
+    if let Some(server) = args.connect {
+        let client = NetClient::connect(server)
+            .unwrap_or_else(|e| exit_with(&format!("unable to connect to {}: {}", server, e)));
+        app.insert_resource(client)
+            .add_system(net_client_system.after(player_input_system))
+            .add_system(net_client_exit_system);
     }
+    app.run();
 }
@@ -0,0 +1,248 @@
+use crate::trainer::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricsFormat {
+    Csv,
+    Jsonl,
+}
+
+pub struct MetricsLog {
+    pub format: MetricsFormat,
+    pub dir: PathBuf,
+}
+
+impl Default for MetricsLog {
+    fn default() -> Self {
+        Self {
+            format: MetricsFormat::Csv,
+            dir: Path::new("metrics").to_path_buf(),
+        }
+    }
+}
+
+impl MetricsLog {
+    pub fn path(&self) -> PathBuf {
+        match self.format {
+            MetricsFormat::Csv => self.dir.join("training.csv"),
+            MetricsFormat::Jsonl => self.dir.join("training.jsonl"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationMetrics {
+    pub generation: i32,
+    pub best: f32,
+    pub mean: f32,
+    pub median: f32,
+    pub worst: f32,
+    pub record_meters: f32,
+    pub best_lap: Option<f32>,
+    pub lap_times: Vec<f32>,
+    pub crashed: usize,
+    pub wall_seconds: f64,
+    pub diversity: f32,
+    // population mean of each weighted fitness term
+    pub terms: Vec<(String, f32)>,
+}
+
+#[derive(Default)]
+pub struct MetricsHistory {
+    pub generations: Vec<GenerationMetrics>,
+}
+
+const CSV_HEADER: &str =
+    "generation,best,mean,median,worst,record_meters,best_lap,laps,crashed,wall_seconds,diversity,terms";
+
+impl GenerationMetrics {
+    pub fn from_event(generation_e: &GenerationEvent) -> Self {
+        let results = &generation_e.results;
+        let mut fitness: Vec<f32> = results.iter().map(|r| r.fitness).collect();
+        fitness.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let n = fitness.len();
+        let median = match n {
+            0 => 0.,
+            n if n % 2 == 0 => (fitness[n / 2 - 1] + fitness[n / 2]) / 2.,
+            n => fitness[n / 2],
+        };
+        let lap_times: Vec<f32> = results
+            .iter()
+            .flat_map(|r| r.lap_times.iter().cloned())
+            .collect();
+        Self {
+            generation: generation_e.generation - 1,
+            best: fitness.last().cloned().unwrap_or(0.),
+            mean: match n {
+                0 => 0.,
+                n => fitness.iter().sum::<f32>() / n as f32,
+            },
+            median,
+            worst: fitness.first().cloned().unwrap_or(0.),
+            record_meters: results.iter().map(|r| r.meters).fold(0., f32::max),
+            best_lap: lap_times.iter().cloned().reduce(f32::min),
+            lap_times,
+            crashed: results
+                .iter()
+                .filter(|r| r.termination.is_some_and(|t| t.is_crash()))
+                .count(),
+            wall_seconds: generation_e.seconds,
+            diversity: generation_e.diversity,
+            terms: mean_terms(results),
+        }
+    }
+
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{:.2},{},{}",
+            self.generation,
+            self.best,
+            self.mean,
+            self.median,
+            self.worst,
+            self.record_meters,
+            self.best_lap.map_or("".to_string(), |l| l.to_string()),
+            self.lap_times.len(),
+            self.crashed,
+            self.wall_seconds,
+            self.diversity,
+            self.terms
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<String>>()
+                .join(";")
+        )
+    }
+}
+
+fn mean_terms(results: &[CarResult]) -> Vec<(String, f32)> {
+    let mut terms: Vec<(String, f32)> = vec![];
+    for (name, value) in results.iter().flat_map(|r| r.breakdown.iter()) {
+        match terms.iter_mut().find(|(n, _)| n == name) {
+            Some((_, sum)) => *sum += value,
+            None => terms.push((name.to_string(), *value)),
+        }
+    }
+    for (_, sum) in terms.iter_mut() {
+        *sum /= results.len() as f32;
+    }
+    terms
+}
+
+pub fn metrics_system(
+    log: Res<MetricsLog>,
+    mut history: ResMut<MetricsHistory>,
+    mut e_generation: EventReader<GenerationEvent>,
+) {
+    for generation_e in e_generation.iter() {
+        let metrics = GenerationMetrics::from_event(generation_e);
+        if let Err(e) = append_metrics(&log, &metrics) {
+            println!("unable to write metrics {}: {}", log.path().display(), e);
+        }
+        history.generations.push(metrics);
+    }
+}
+
+fn append_metrics(log: &MetricsLog, metrics: &GenerationMetrics) -> std::io::Result<()> {
+    fs::create_dir_all(&log.dir)?;
+    let path = log.path();
+    let is_new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    match log.format {
+        MetricsFormat::Csv => {
+            if is_new {
+                writeln!(file, "{}", CSV_HEADER)?;
+            }
+            writeln!(file, "{}", metrics.csv_row())
+        }
+        MetricsFormat::Jsonl => writeln!(file, "{}", serde_json::to_string(metrics)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::termination::Termination;
+
+    fn result(id: usize, fitness: f32, termination: Option<Termination>) -> CarResult {
+        CarResult {
+            id,
+            fitness,
+            breakdown: vec![("distance", fitness), ("off track", -1.)],
+            meters: fitness * 10.,
+            lap_times: vec![],
+            termination,
+        }
+    }
+
+    fn event(results: Vec<CarResult>) -> GenerationEvent {
+        GenerationEvent {
+            generation: 4,
+            track: "test".to_string(),
+            results,
+            diversity: 0.25,
+            seconds: 12.,
+        }
+    }
+
+    #[test]
+    fn an_odd_population_takes_the_middle_fitness() {
+        let metrics = GenerationMetrics::from_event(&event(vec![
+            result(0, 3., None),
+            result(1, 9., Some(Termination::Flipped)),
+            result(2, 1., Some(Termination::NoProgress)),
+            result(3, 7., Some(Termination::WallContact)),
+            result(4, 5., None),
+        ]));
+        assert_eq!(metrics.generation, 3);
+        assert_eq!(metrics.median, 5.);
+        assert_eq!(metrics.mean, 5.);
+        assert_eq!(metrics.best, 9.);
+        assert_eq!(metrics.worst, 1.);
+        assert_eq!(metrics.record_meters, 90.);
+        // no progress is not a crash
+        assert_eq!(metrics.crashed, 2);
+        assert_eq!(metrics.diversity, 0.25);
+        assert_eq!(
+            metrics.terms,
+            vec![("distance".to_string(), 5.), ("off track".to_string(), -1.)]
+        );
+    }
+
+    #[test]
+    fn an_even_population_averages_the_middle_two() {
+        let metrics = GenerationMetrics::from_event(&event(vec![
+            result(0, 4., None),
+            result(1, 1., None),
+            result(2, 10., None),
+            result(3, 2., None),
+        ]));
+        assert_eq!(metrics.median, 3.);
+        assert_eq!(metrics.mean, 4.25);
+        assert_eq!(metrics.best, 10.);
+        assert_eq!(metrics.worst, 1.);
+        assert_eq!(metrics.crashed, 0);
+    }
+
+    #[test]
+    fn an_empty_population_logs_zeros() {
+        let metrics = GenerationMetrics::from_event(&event(vec![]));
+        assert_eq!(metrics.median, 0.);
+        assert_eq!(metrics.mean, 0.);
+        assert_eq!(metrics.best, 0.);
+        assert_eq!(metrics.worst, 0.);
+        assert_eq!(metrics.crashed, 0);
+        assert!(metrics.best_lap.is_none());
+        assert!(metrics.terms.is_empty());
+        assert_eq!(
+            metrics.csv_row().split(',').count(),
+            CSV_HEADER.split(',').count()
+        );
+    }
+}
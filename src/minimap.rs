@@ -0,0 +1,379 @@
+use crate::{
+    car::*,
+    chart::place_segment,
+    config::{Config, FollowMode},
+    progress::*,
+    race::*,
+    termination::CarTermination,
+};
+use bevy::prelude::*;
+
+const MINIMAP: f32 = 220.;
+const MARGIN: f32 = 10.;
+const MAX_SEGMENTS: usize = 160;
+const SECTORS: usize = 3;
+const DOT: f32 = 8.;
+const DOT_FOLLOWED: f32 = 12.;
+
+#[derive(Component, Default)]
+pub struct Minimap {
+    // first polyline point the map was drawn for, changes with the curriculum track
+    pub track_start: Option<Vec3>,
+    pub min: Vec2,
+    pub scale: f32,
+}
+
+impl Minimap {
+    // scale the track's top view into the map, keeping the aspect ratio
+    fn fit(&mut self, points: &[Vec3]) {
+        let min = points
+            .iter()
+            .fold(Vec2::splat(f32::MAX), |m, p| m.min(Vec2::new(p.x, p.z)));
+        let max = points
+            .iter()
+            .fold(Vec2::splat(f32::MIN), |m, p| m.max(Vec2::new(p.x, p.z)));
+        let extent = (max - min).max_element().max(1.);
+        self.min = Vec2::new(min.x, max.y);
+        self.scale = (MINIMAP - 2. * MARGIN) / extent;
+    }
+
+    // top view, x to the right and -z up
+    fn project(&self, p: Vec3) -> Vec2 {
+        Vec2::new(p.x - self.min.x, self.min.y - p.z) * self.scale + Vec2::splat(MARGIN)
+    }
+}
+
+// car of the dot nearest to a point on the map, followed dots drawn bigger win ties
+fn dot_at(point: Vec2, dots: &[(Vec2, f32, Entity)]) -> Option<Entity> {
+    dots.iter()
+        .filter(|(center, size, _)| point.distance(*center) <= size / 2. + 2.)
+        .min_by(|a, b| {
+            let da = point.distance(a.0) - a.1;
+            let db = point.distance(b.0) - b.1;
+            da.partial_cmp(&db).unwrap()
+        })
+        .map(|(_, _, car)| *car)
+}
+
+#[derive(Component)]
+pub struct MinimapTrack;
+
+#[derive(Component)]
+pub struct MinimapPart;
+
+#[derive(Component)]
+pub struct MinimapDot {
+    pub index: usize,
+    pub car: Option<Entity>,
+    // map position and size the dot was last drawn at
+    pub center: Vec2,
+    pub size: f32,
+}
+
+pub fn minimap_start_system(mut commands: Commands, config: Res<Config>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(2.0),
+                    right: Val::Px(390.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(MINIMAP), Val::Px(MINIMAP)),
+                ..default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
+            ..default()
+        })
+        .insert(Name::new("Minimap"))
+        .insert(Minimap::default())
+        .with_children(|parent| {
+            // track below the dots
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                        ..default()
+                    },
+                    color: UiColor(Color::NONE),
+                    ..default()
+                })
+                .insert(MinimapTrack);
+            for index in 0..config.cars_count {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            size: Size::new(Val::Px(DOT), Val::Px(DOT)),
+                            ..default()
+                        },
+                        color: UiColor(Color::NONE),
+                        ..default()
+                    })
+                    .insert(MinimapDot {
+                        index,
+                        car: None,
+                        center: Vec2::ZERO,
+                        size: DOT,
+                    });
+            }
+        });
+}
+
+fn spawn_minimap_track(
+    commands: &mut Commands,
+    track_e: Entity,
+    minimap: &mut Minimap,
+    config: &Config,
+) {
+    let polyline = match &config.polyline {
+        Some(polyline) => polyline,
+        None => return,
+    };
+    let points: Vec<Vec3> = polyline
+        .vertices()
+        .iter()
+        .map(|p| Vec3::new(p.x, p.y, p.z))
+        .collect();
+    minimap.fit(&points);
+
+    let step = (points.len() / MAX_SEGMENTS).max(1);
+    let mut outline: Vec<Vec2> = points
+        .iter()
+        .step_by(step)
+        .map(|p| minimap.project(*p))
+        .collect();
+    if let Some(first) = outline.first().cloned() {
+        outline.push(first);
+    }
+    let mut marks: Vec<(Vec3, Vec3, Color, f32)> = vec![];
+    for sector in 0..SECTORS {
+        let meters = config.meters_shift + config.meters_total * sector as f32 / SECTORS as f32;
+        if let Some((p, dir)) = polyline_point(config, meters) {
+            match sector {
+                0 => marks.push((p, dir, Color::WHITE, 14.)),
+                _ => marks.push((p, dir, Color::YELLOW, 10.)),
+            }
+        }
+    }
+
+    commands.entity(track_e).with_children(|parent| {
+        let mut spawn_line = |a: Vec2, b: Vec2, width: f32, color: Color| {
+            let mut style = Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            };
+            let mut transform = Transform::default();
+            place_segment(&mut style, &mut transform, a, b, width);
+            parent
+                .spawn_bundle(NodeBundle {
+                    style,
+                    transform,
+                    color: UiColor(color),
+                    ..default()
+                })
+                .insert(MinimapPart);
+        };
+        for pair in outline.windows(2) {
+            spawn_line(pair[0], pair[1], 2., Color::rgba(0.8, 0.8, 0.8, 0.8));
+        }
+        // start/finish and sector lines across the track
+        for (p, dir, color, length) in marks {
+            let c = minimap.project(p);
+            let across = Vec2::new(-dir.z, -dir.x).normalize_or_zero() * length / 2.;
+            spawn_line(c - across, c + across, 2., color);
+        }
+    });
+}
+
+fn dot_color(rank: usize, cars: usize, termination: Option<&CarTermination>) -> Color {
+    match termination.and_then(|t| t.done) {
+        Some(t) if t.is_crash() => Color::RED,
+        Some(_) => Color::GRAY,
+        None if rank == 0 => Color::GOLD,
+        None => {
+            let t = rank as f32 / cars.max(2) as f32;
+            Color::rgb(0.3 + 0.6 * (1. - t), 0.5 + 0.3 * (1. - t), 0.9)
+        }
+    }
+}
+
+pub fn minimap_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    config: Res<Config>,
+    race: Res<Race>,
+    mut q_minimap: Query<(&mut Minimap, &mut Style), Without<MinimapDot>>,
+    q_track: Query<Entity, With<MinimapTrack>>,
+    q_parts: Query<Entity, With<MinimapPart>>,
+    q_cars: Query<
+        (
+            Entity,
+            &CarId,
+            &Transform,
+            &CarProgress,
+            Option<&CarTermination>,
+        ),
+        With<Car>,
+    >,
+    mut q_dots: Query<(&mut MinimapDot, &mut Style, &mut UiColor), Without<Minimap>>,
+) {
+    let (mut minimap, mut style) = q_minimap.single_mut();
+    if keys.just_pressed(KeyCode::M) {
+        style.display = match style.display {
+            Display::None => Display::Flex,
+            Display::Flex => Display::None,
+        };
+    }
+    if style.display == Display::None {
+        return;
+    }
+
+    let track_start = config
+        .polyline
+        .as_ref()
+        .map(|p| p.segment(0).a)
+        .map(|a| Vec3::new(a.x, a.y, a.z));
+    if track_start != minimap.track_start {
+        for part in q_parts.iter() {
+            commands.entity(part).despawn_recursive();
+        }
+        spawn_minimap_track(&mut commands, q_track.single(), &mut minimap, &config);
+        minimap.track_start = track_start;
+    }
+
+    let standings = race_standings(
+        &config,
+        &race,
+        q_cars
+            .iter()
+            .map(|(e, _, _, progress, _)| (e, progress.meters)),
+    );
+    let followed = config.camera_follow.or(config.hid_car);
+    for (mut dot, mut style, mut color) in q_dots.iter_mut() {
+        let car = q_cars.iter().find(|(_, id, ..)| id.0 == dot.index);
+        dot.car = car.map(|(e, ..)| e);
+        let (e, _, transform, _, termination) = match car {
+            Some(car) => car,
+            None => {
+                *color = UiColor(Color::NONE);
+                continue;
+            }
+        };
+        let size = match Some(e) == followed {
+            true => DOT_FOLLOWED,
+            false => DOT,
+        };
+        let p = minimap.project(transform.translation);
+        dot.center = p;
+        dot.size = size;
+        style.size = Size::new(Val::Px(size), Val::Px(size));
+        style.position.left = Val::Px(p.x - size / 2.);
+        style.position.bottom = Val::Px(p.y - size / 2.);
+        let rank = standings.iter().position(|s| s.entity == e).unwrap_or(0);
+        *color = UiColor(match Some(e) == followed {
+            true => Color::WHITE,
+            false => dot_color(rank, standings.len(), termination),
+        });
+    }
+}
+
+pub fn minimap_click_system(
+    mut config: ResMut<Config>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    q_minimap: Query<(&Node, &GlobalTransform, &Style), With<Minimap>>,
+    q_dots: Query<&MinimapDot>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let (node, transform, style) = q_minimap.single();
+    let cursor = match windows.get_primary().and_then(|w| w.cursor_position()) {
+        Some(cursor) if style.display != Display::None => cursor,
+        _ => return,
+    };
+    // both the cursor and the dots count from the bottom left corner
+    let point = cursor - (transform.translation().truncate() - node.size / 2.);
+    let dots: Vec<(Vec2, f32, Entity)> = q_dots
+        .iter()
+        .filter_map(|dot| dot.car.map(|car| (dot.center, dot.size, car)))
+        .collect();
+    if let Some(car) = dot_at(point, &dots) {
+        config.follow_mode = FollowMode::Car;
+        config.camera_follow = Some(car);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Vec<Vec3> {
+        vec![
+            Vec3::new(-50., 0., -100.),
+            Vec3::new(50., 0., -100.),
+            Vec3::new(50., 0., 100.),
+            Vec3::new(-50., 0., 100.),
+        ]
+    }
+
+    #[test]
+    fn the_track_fits_inside_the_margins() {
+        let mut minimap = Minimap::default();
+        minimap.fit(&square());
+        // the longer z side spans the map, x keeps the same scale
+        assert_eq!(minimap.scale, (MINIMAP - 2. * MARGIN) / 200.);
+        assert_eq!(
+            minimap.project(Vec3::new(-50., 0., 100.)),
+            Vec2::splat(MARGIN)
+        );
+        assert_eq!(
+            minimap.project(Vec3::new(50., 0., -100.)),
+            Vec2::new(MARGIN + 100. * minimap.scale, MINIMAP - MARGIN)
+        );
+    }
+
+    #[test]
+    fn forward_on_the_track_is_up_on_the_map() {
+        let mut minimap = Minimap::default();
+        minimap.fit(&square());
+        let near = minimap.project(Vec3::new(0., 5., 0.));
+        let far = minimap.project(Vec3::new(0., 0., -10.));
+        assert_eq!(near.x, far.x);
+        assert!(far.y > near.y);
+        assert_eq!(near, Vec2::new(MARGIN + 50. * minimap.scale, MINIMAP / 2.));
+    }
+
+    #[test]
+    fn a_single_point_does_not_divide_by_zero() {
+        let mut minimap = Minimap::default();
+        minimap.fit(&[Vec3::new(3., 0., 4.)]);
+        assert!(minimap.scale.is_finite());
+        assert_eq!(minimap.project(Vec3::new(3., 0., 4.)), Vec2::splat(MARGIN));
+    }
+
+    #[test]
+    fn a_click_picks_the_nearest_dot_under_it() {
+        let a = Entity::from_raw(1);
+        let b = Entity::from_raw(2);
+        let dots = [(Vec2::new(50., 50.), DOT, a), (Vec2::new(56., 50.), DOT, b)];
+        assert_eq!(dot_at(Vec2::new(51., 50.), &dots), Some(a));
+        assert_eq!(dot_at(Vec2::new(55., 51.), &dots), Some(b));
+        assert_eq!(dot_at(Vec2::new(50., 80.), &dots), None);
+        assert_eq!(dot_at(Vec2::new(50., 50.), &[]), None);
+    }
+
+    #[test]
+    fn a_followed_dot_is_easier_to_hit() {
+        let a = Entity::from_raw(1);
+        let b = Entity::from_raw(2);
+        let point = Vec2::new(50., 57.);
+        let small = [(Vec2::new(50., 50.), DOT, a)];
+        let big = [(Vec2::new(50., 50.), DOT_FOLLOWED, b)];
+        assert_eq!(dot_at(point, &small), None);
+        assert_eq!(dot_at(point, &big), Some(b));
+    }
+}
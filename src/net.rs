@@ -0,0 +1,804 @@
+use crate::{
+    brain::*, car::*, config::Config, contact::*, fitness::*, input::Player, noise::Noise,
+    progress::*, race::*, rng::SimRng, termination::*, track::*, trainer::*,
+};
+use bevy::{
+    app::{AppExit, ScheduleRunnerSettings},
+    asset::AssetPlugin,
+    hierarchy::HierarchyPlugin,
+    prelude::*,
+    scene::ScenePlugin,
+    transform::TransformPlugin,
+};
+use bevy_rapier3d::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+pub const DEFAULT_PORT: u16 = 7878;
+const TICK_RATE: f64 = 60.;
+const PACKET_SIZE: usize = 65536;
+const JOIN_INTERVAL: f64 = 1.;
+const TIMEOUT: f64 = 5.;
+// remote cars are drawn this far in the past, between two received states
+const INTERPOLATION_DELAY: f64 = 0.1;
+const SNAPSHOTS: usize = 32;
+// own car prediction is corrected by the server error, or snapped when too far off
+const RECONCILE_EPSILON: f32 = 0.01;
+const SNAP_DISTANCE: f32 = 3.;
+// a second of inputs at 60 fps, older ones were lost with their acks
+const PENDING_INPUTS: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NetInput {
+    pub seq: u32,
+    pub gas: f32,
+    pub brake: f32,
+    pub steering: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetCarState {
+    pub id: usize,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub linvel: [f32; 3],
+    pub angvel: [f32; 3],
+    pub meters: f32,
+    pub offset: f32,
+}
+
+impl NetCarState {
+    fn translation(&self) -> Vec3 {
+        Vec3::from(self.translation)
+    }
+    fn rotation(&self) -> Quat {
+        Quat::from_array(self.rotation)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Join,
+    Input(NetInput),
+    Leave,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome {
+        car: usize,
+    },
+    Full,
+    State {
+        tick: u64,
+        // last input of this client the server applied
+        ack: u32,
+        cars: Vec<NetCarState>,
+    },
+}
+
+fn send<T: Serialize>(socket: &UdpSocket, addr: Option<SocketAddr>, message: &T) {
+    let bytes = match serde_json::to_vec(message) {
+        Ok(bytes) => bytes,
+        Err(e) => return println!("unable to encode message: {}", e),
+    };
+    let sent = match addr {
+        Some(addr) => socket.send_to(&bytes, addr),
+        None => socket.send(&bytes),
+    };
+    if let Err(e) = sent {
+        if e.kind() != ErrorKind::WouldBlock {
+            println!("unable to send: {}", e);
+        }
+    }
+}
+
+// next datagram that decodes, None once the socket is drained
+fn receive<T: DeserializeOwned>(socket: &UdpSocket, buf: &mut [u8]) -> Option<(SocketAddr, T)> {
+    loop {
+        match socket.recv_from(buf) {
+            Ok((len, addr)) => match serde_json::from_slice(&buf[..len]) {
+                Ok(message) => return Some((addr, message)),
+                Err(e) => println!("bad packet from {}: {}", addr, e),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+            // a client that went away makes some platforms report the next read
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                println!("unable to receive: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+pub struct RemoteDriver {
+    pub addr: SocketAddr,
+    pub car: usize,
+    pub input: NetInput,
+    pub heard_at: f64,
+}
+
+pub struct NetServer {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    pub drivers: Vec<RemoteDriver>,
+    pub tick: u64,
+}
+
+impl NetServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        println!("server listening on {}", socket.local_addr()?);
+        Ok(Self {
+            socket,
+            buf: vec![0; PACKET_SIZE],
+            drivers: vec![],
+            tick: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+pub fn net_server_start_system(
+    mut config: ResMut<Config>,
+    mut race: ResMut<Race>,
+    time: Res<Time>,
+) {
+    race_begin(&mut config, &mut race, time.seconds_since_startup());
+}
+
+pub fn net_server_receive_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut server: ResMut<NetServer>,
+    mut q_cars: Query<(&CarId, &mut Car)>,
+) {
+    let seconds = time.seconds_since_startup();
+    let NetServer {
+        socket,
+        buf,
+        drivers,
+        ..
+    } = &mut *server;
+    while let Some((addr, message)) = receive::<ClientMessage>(socket, buf) {
+        let driver = drivers.iter().position(|d| d.addr == addr);
+        match (message, driver) {
+            (ClientMessage::Join, Some(i)) => {
+                drivers[i].heard_at = seconds;
+                send(
+                    socket,
+                    Some(addr),
+                    &ServerMessage::Welcome {
+                        car: drivers[i].car,
+                    },
+                );
+            }
+            (ClientMessage::Join, None) => {
+                let free = (0..config.cars_count).find(|id| drivers.iter().all(|d| d.car != *id));
+                match free {
+                    Some(car) => {
+                        println!("{} drives car {}", addr, car);
+                        drivers.push(RemoteDriver {
+                            addr,
+                            car,
+                            input: NetInput::default(),
+                            heard_at: seconds,
+                        });
+                        send(socket, Some(addr), &ServerMessage::Welcome { car });
+                    }
+                    None => send(socket, Some(addr), &ServerMessage::Full),
+                }
+            }
+            (ClientMessage::Input(input), Some(i)) => {
+                let driver = &mut drivers[i];
+                driver.heard_at = seconds;
+                // datagrams may come out of order
+                if input.seq > driver.input.seq {
+                    driver.input = input;
+                }
+            }
+            (ClientMessage::Leave, Some(i)) => {
+                println!("{} left", addr);
+                release_car(&config, &mut q_cars, drivers.remove(i).car);
+            }
+            (_, None) => {}
+        }
+    }
+
+    let mut i = 0;
+    while i < drivers.len() {
+        if seconds - drivers[i].heard_at > TIMEOUT {
+            println!("{} timed out", drivers[i].addr);
+            release_car(&config, &mut q_cars, drivers.remove(i).car);
+            continue;
+        }
+        i += 1;
+    }
+
+    for (id, mut car) in q_cars.iter_mut() {
+        if let Some(driver) = drivers.iter().find(|d| d.car == id.0) {
+            car.use_brain = false;
+            car.gas = driver.input.gas;
+            car.brake = driver.input.brake;
+            car.steering = driver.input.steering;
+        }
+    }
+}
+
+// back to the brain
+fn release_car(config: &Config, q_cars: &mut Query<(&CarId, &mut Car)>, car_id: usize) {
+    for (id, mut car) in q_cars.iter_mut() {
+        if id.0 == car_id {
+            car.use_brain = config.use_brain;
+        }
+    }
+}
+
+pub fn net_server_send_system(
+    mut server: ResMut<NetServer>,
+    q_cars: Query<(&CarId, &Transform, &Velocity, &CarProgress)>,
+) {
+    server.tick += 1;
+    if server.drivers.is_empty() {
+        return;
+    }
+    let mut cars: Vec<NetCarState> = q_cars
+        .iter()
+        .map(|(id, t, v, progress)| NetCarState {
+            id: id.0,
+            translation: t.translation.to_array(),
+            rotation: t.rotation.to_array(),
+            linvel: v.linvel.to_array(),
+            angvel: v.angvel.to_array(),
+            meters: progress.meters,
+            offset: progress.offset,
+        })
+        .collect();
+    cars.sort_by_key(|c| c.id);
+    let mut state = ServerMessage::State {
+        tick: server.tick,
+        ack: 0,
+        cars,
+    };
+    for driver in server.drivers.iter() {
+        if let ServerMessage::State { ref mut ack, .. } = state {
+            *ack = driver.input.seq;
+        }
+        send(&server.socket, Some(driver.addr), &state);
+    }
+}
+
+// same simulation as a training world, no window, race rules, cars open to remote drivers
+pub fn server_app(
+    port: u16,
+    config: Config,
+    start_brains: StartBrains,
+    noise: Noise,
+) -> std::io::Result<App> {
+    let server = NetServer::bind(("0.0.0.0", port))?;
+    let mut app = App::new();
+    app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+        1. / TICK_RATE,
+    )))
+    .insert_resource(server)
+    .insert_resource(SimRng::new(config.seed))
+    .insert_resource(config)
+    .insert_resource(noise)
+    .insert_resource(Trainer::default())
+    .insert_resource(Race::default())
+    .insert_resource(Fitness::default())
+    .insert_resource(start_brains)
+    .insert_resource(TerminationRules::default())
+    .init_resource::<BrainActivity>()
+    .add_event::<GenerationEvent>()
+    .add_plugins(MinimalPlugins)
+    .add_plugin(TransformPlugin)
+    .add_plugin(HierarchyPlugin)
+    .add_plugin(AssetPlugin)
+    .add_plugin(ScenePlugin)
+    .add_asset::<Mesh>()
+    .add_asset::<StandardMaterial>()
+    .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+    .add_startup_system(track_start_system)
+    .add_startup_system(track_polyline_start_system)
+    .add_startup_system(car_start_system)
+    // race_begin takes the brains off, the ai cars must have spawned with theirs
+    .add_startup_system(net_server_start_system.after(car_start_system))
+    .add_system(net_server_receive_system)
+    .add_system(car_brain_system.after(net_server_receive_system))
+    .add_system(trainer_system)
+    .add_system(reset_pos_system)
+    .add_system(progress_system)
+    .add_system(reset_force_system)
+    .add_system(race_system)
+    .add_system(car_contacts_system)
+    .add_system(car_stats_system)
+    .add_system(car_fitness_system)
+    .add_system_to_stage(CoreStage::PostUpdate, car_contact_forces_system)
+    .add_system_to_stage(CoreStage::PostUpdate, net_server_send_system);
+    Ok(app)
+}
+
+struct Snapshot {
+    tick: u64,
+    cars: Vec<NetCarState>,
+}
+
+// server states in tick order, datagrams may arrive late or twice
+#[derive(Default)]
+struct Snapshots {
+    buf: VecDeque<Snapshot>,
+    // newest tick and when it arrived, the client's idea of the server clock
+    newest: Option<(u64, f64)>,
+}
+
+impl Snapshots {
+    // false for a state older than everything kept or already seen
+    fn insert(&mut self, tick: u64, cars: Vec<NetCarState>, seconds: f64) -> bool {
+        if self.buf.front().is_some_and(|s| tick <= s.tick) && self.buf.len() >= SNAPSHOTS {
+            return false;
+        }
+        let i = match self.buf.binary_search_by_key(&tick, |s| s.tick) {
+            Ok(_) => return false,
+            Err(i) => i,
+        };
+        self.buf.insert(i, Snapshot { tick, cars });
+        if self.buf.len() > SNAPSHOTS {
+            self.buf.pop_front();
+        }
+        if self.newest.is_none_or(|(newest, _)| tick > newest) {
+            self.newest = Some((tick, seconds));
+        }
+        true
+    }
+
+    fn clear(&mut self) {
+        *self = Snapshots::default();
+    }
+
+    // server time now, extrapolated from the newest state
+    fn server_seconds(&self, seconds: f64) -> Option<f64> {
+        let (tick, received_at) = self.newest?;
+        Some(tick as f64 / TICK_RATE + seconds - received_at)
+    }
+
+    // car states at the given server time, None until two states arrived
+    fn interpolated(&self, at: f64) -> Option<Vec<NetCarState>> {
+        let newest = self.buf.back()?;
+        let at_tick = at * TICK_RATE;
+        let (a, b) = match self.buf.iter().position(|s| s.tick as f64 > at_tick) {
+            Some(0) => return None,
+            Some(i) => (&self.buf[i - 1], &self.buf[i]),
+            None => return Some(newest.cars.clone()),
+        };
+        let t = ((at_tick - a.tick as f64) / (b.tick - a.tick) as f64).clamp(0., 1.) as f32;
+        Some(
+            b.cars
+                .iter()
+                .map(|cb| match a.cars.iter().find(|ca| ca.id == cb.id) {
+                    Some(ca) => NetCarState {
+                        translation: ca.translation().lerp(cb.translation(), t).to_array(),
+                        rotation: ca.rotation().slerp(cb.rotation(), t).to_array(),
+                        linvel: Vec3::from(ca.linvel)
+                            .lerp(Vec3::from(cb.linvel), t)
+                            .to_array(),
+                        meters: ca.meters + (cb.meters - ca.meters) * t,
+                        offset: ca.offset + (cb.offset - ca.offset) * t,
+                        ..cb.clone()
+                    },
+                    None => cb.clone(),
+                })
+                .collect(),
+        )
+    }
+}
+
+// own car state right before an input was applied
+#[derive(Debug, Clone, Copy)]
+struct Predicted {
+    seq: u32,
+    translation: Vec3,
+    rotation: Quat,
+    linvel: Vec3,
+    angvel: Vec3,
+}
+
+pub struct NetClient {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    pub car: Option<usize>,
+    seq: u32,
+    pub ack: u32,
+    join_sent_at: f64,
+    heard_at: f64,
+    snapshots: Snapshots,
+    // inputs the server has not acknowledged yet, oldest first
+    pending: VecDeque<Predicted>,
+}
+
+impl NetClient {
+    pub fn connect(server: SocketAddr) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(match server.is_ipv4() {
+            true => "0.0.0.0:0",
+            false => "[::]:0",
+        })?;
+        socket.connect(server)?;
+        socket.set_nonblocking(true)?;
+        println!("connecting to {}", server);
+        Ok(Self {
+            socket,
+            buf: vec![0; PACKET_SIZE],
+            car: None,
+            seq: 0,
+            ack: 0,
+            join_sent_at: f64::MIN,
+            heard_at: 0.,
+            snapshots: Snapshots::default(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    // rapier can't step one body on its own, so instead of simulating the unacknowledged
+    // inputs again, the motion they produced here is kept and only the error the server
+    // found in the acknowledged part is applied
+    fn reconcile(&mut self, ack: u32, state: &NetCarState, t: &mut Transform, v: &mut Velocity) {
+        if ack <= self.ack {
+            return;
+        }
+        self.ack = ack;
+        while self.pending.front().is_some_and(|p| p.seq <= ack) {
+            self.pending.pop_front();
+        }
+        // the state before the next input is the state after the acknowledged one
+        let predicted = self.pending.front().copied().unwrap_or(Predicted {
+            seq: ack,
+            translation: t.translation,
+            rotation: t.rotation,
+            linvel: v.linvel,
+            angvel: v.angvel,
+        });
+        let translation = state.translation() - predicted.translation;
+        if translation.length() < RECONCILE_EPSILON {
+            return;
+        }
+        let rotation = state.rotation() * predicted.rotation.inverse();
+        let linvel = Vec3::from(state.linvel) - predicted.linvel;
+        let angvel = Vec3::from(state.angvel) - predicted.angvel;
+        t.translation += translation;
+        t.rotation = (rotation * t.rotation).normalize();
+        v.linvel += linvel;
+        v.angvel += angvel;
+        // the later predictions were made from the same wrong start
+        for p in self.pending.iter_mut() {
+            p.translation += translation;
+            p.rotation = (rotation * p.rotation).normalize();
+            p.linvel += linvel;
+            p.angvel += angvel;
+        }
+    }
+}
+
+// hand the player, camera and dash over to the car the server gave us
+fn take_car(
+    commands: &mut Commands,
+    config: &mut Config,
+    q_player: &Query<(Entity, &Player)>,
+    q_cars: &mut Query<(
+        Entity,
+        &CarId,
+        &mut Car,
+        &mut Transform,
+        &mut Velocity,
+        &mut CarProgress,
+        &mut RigidBody,
+    )>,
+    car_id: usize,
+) {
+    let mut player = Player::new(0);
+    for (e, p) in q_player.iter() {
+        if p.index == 0 {
+            player.input = p.input;
+            commands.entity(e).remove::<Player>().remove::<HID>();
+        }
+    }
+    for (e, id, mut car, .., mut body) in q_cars.iter_mut() {
+        if id.0 == car_id {
+            *body = RigidBody::Dynamic;
+            car.use_brain = false;
+            commands.entity(e).insert(HID).remove::<RemoteCar>();
+            config.hid_car = Some(e);
+            config.camera_follow = Some(e);
+        } else {
+            // moved by the server states only
+            *body = RigidBody::KinematicPositionBased;
+            commands.entity(e).insert(RemoteCar);
+        }
+    }
+    if let Some(e) = config.hid_car {
+        commands.entity(e).insert(player);
+    }
+}
+
+pub fn net_client_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut config: ResMut<Config>,
+    mut client: ResMut<NetClient>,
+    q_player: Query<(Entity, &Player)>,
+    mut q_cars: Query<(
+        Entity,
+        &CarId,
+        &mut Car,
+        &mut Transform,
+        &mut Velocity,
+        &mut CarProgress,
+        &mut RigidBody,
+    )>,
+) {
+    let seconds = time.seconds_since_startup();
+    if client.car.is_none() && seconds - client.join_sent_at > JOIN_INTERVAL {
+        send(&client.socket, None, &ClientMessage::Join);
+        client.join_sent_at = seconds;
+    }
+
+    // newest own state by tick, with the input it acknowledges
+    let mut fresh: Option<(u64, u32, Vec<NetCarState>)> = None;
+    let NetClient { socket, buf, .. } = &mut *client;
+    let mut messages = vec![];
+    while let Some((_, message)) = receive::<ServerMessage>(socket, buf) {
+        messages.push(message);
+    }
+    for message in messages {
+        client.heard_at = seconds;
+        match message {
+            ServerMessage::Welcome { car } => {
+                if client.car != Some(car) {
+                    println!("driving car {}", car);
+                    client.car = Some(car);
+                    take_car(&mut commands, &mut config, &q_player, &mut q_cars, car);
+                }
+            }
+            ServerMessage::Full => println!("server is full"),
+            ServerMessage::State { tick, ack, cars } => {
+                if fresh.as_ref().is_none_or(|(newest, ..)| tick > *newest) {
+                    fresh = Some((tick, ack, cars.clone()));
+                }
+                client.snapshots.insert(tick, cars, seconds);
+            }
+        }
+    }
+
+    let own = match client.car {
+        Some(own) => own,
+        None => return,
+    };
+    if seconds - client.heard_at > TIMEOUT {
+        println!("lost the server, joining again");
+        client.car = None;
+        client.snapshots.clear();
+        client.pending.clear();
+        return;
+    }
+
+    let remote = client
+        .snapshots
+        .server_seconds(seconds)
+        .and_then(|now| client.snapshots.interpolated(now - INTERPOLATION_DELAY));
+    for (_, id, mut car, mut t, mut v, mut progress, _) in q_cars.iter_mut() {
+        if id.0 == own {
+            let server_state = fresh
+                .as_ref()
+                .and_then(|(_, ack, cars)| Some((*ack, cars.iter().find(|c| c.id == own)?)));
+            if let Some((ack, state)) = server_state {
+                if state.translation().distance(t.translation) > SNAP_DISTANCE {
+                    // too far off to correct, start over from the server
+                    t.translation = state.translation();
+                    t.rotation = state.rotation();
+                    v.linvel = Vec3::from(state.linvel);
+                    v.angvel = Vec3::from(state.angvel);
+                    client.pending.clear();
+                    client.ack = client.ack.max(ack);
+                } else {
+                    client.reconcile(ack, state, &mut t, &mut v);
+                }
+            }
+            // predicted locally, the input goes to the server as it is applied here
+            car.use_brain = false;
+            client.seq += 1;
+            let input = NetInput {
+                seq: client.seq,
+                gas: car.gas,
+                brake: car.brake,
+                steering: car.steering,
+            };
+            client.pending.push_back(Predicted {
+                seq: input.seq,
+                translation: t.translation,
+                rotation: t.rotation,
+                linvel: v.linvel,
+                angvel: v.angvel,
+            });
+            if client.pending.len() > PENDING_INPUTS {
+                client.pending.pop_front();
+            }
+            send(&client.socket, None, &ClientMessage::Input(input));
+            continue;
+        }
+        let state = remote
+            .as_ref()
+            .and_then(|cars| cars.iter().find(|c| c.id == id.0));
+        if let Some(state) = state {
+            t.translation = state.translation();
+            t.rotation = state.rotation();
+            v.linvel = Vec3::from(state.linvel);
+            v.angvel = Vec3::from(state.angvel);
+            progress.meters = state.meters;
+            progress.offset = state.offset;
+        }
+    }
+}
+
+pub fn net_client_exit_system(client: Res<NetClient>, mut e_exit: EventReader<AppExit>) {
+    if e_exit.iter().next().is_some() {
+        send(&client.socket, None, &ClientMessage::Leave);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Instant};
+
+    fn car_state(id: usize, x: f32) -> NetCarState {
+        NetCarState {
+            id,
+            translation: [x, 0., 0.],
+            rotation: Quat::IDENTITY.to_array(),
+            linvel: [0.; 3],
+            angvel: [0.; 3],
+            meters: x,
+            offset: 0.,
+        }
+    }
+
+    #[test]
+    fn interpolated_goes_by_tick_not_arrival() {
+        let mut snapshots = Snapshots::default();
+        assert!(snapshots.insert(60, vec![car_state(0, 0.)], 1.));
+        // arrives after 66 but is older
+        assert!(snapshots.insert(66, vec![car_state(0, 6.)], 1.1));
+        assert!(snapshots.insert(63, vec![car_state(0, 3.)], 1.2));
+        assert!(!snapshots.insert(63, vec![car_state(0, 3.)], 1.3));
+
+        let at = |tick: f64| tick / TICK_RATE;
+        assert!(snapshots.interpolated(at(59.)).is_none());
+        let cars = snapshots.interpolated(at(61.5)).unwrap();
+        assert!((cars[0].translation[0] - 1.5).abs() < 1e-4);
+        assert!((cars[0].meters - 1.5).abs() < 1e-4);
+        let cars = snapshots.interpolated(at(64.)).unwrap();
+        assert!((cars[0].translation[0] - 4.).abs() < 1e-4);
+        // past the newest state the newest is kept
+        let cars = snapshots.interpolated(at(80.)).unwrap();
+        assert_eq!(cars[0].translation[0], 6.);
+        // the clock follows the newest tick, not the last datagram
+        assert_eq!(snapshots.server_seconds(1.1), Some(at(66.)));
+    }
+
+    #[test]
+    fn reconcile_applies_only_the_acknowledged_error() {
+        let mut client = NetClient::connect("127.0.0.1:9".parse().unwrap()).unwrap();
+        for seq in 1..=3 {
+            client.pending.push_back(Predicted {
+                seq,
+                translation: Vec3::X * seq as f32,
+                rotation: Quat::IDENTITY,
+                linvel: Vec3::ZERO,
+                angvel: Vec3::ZERO,
+            });
+        }
+        let mut t = Transform::from_translation(Vec3::X * 4.);
+        let mut v = Velocity::zero();
+        // after input 1 the server has the car half a meter further than predicted
+        client.reconcile(1, &car_state(0, 2.5), &mut t, &mut v);
+        assert_eq!(client.ack, 1);
+        assert_eq!(t.translation, Vec3::X * 4.5);
+        assert_eq!(client.pending.len(), 2);
+        assert_eq!(client.pending[0].translation, Vec3::X * 2.5);
+        // the same error is not applied twice
+        client.reconcile(2, &car_state(0, 3.5), &mut t, &mut v);
+        assert_eq!(t.translation, Vec3::X * 4.5);
+        // stale acks are ignored
+        client.reconcile(1, &car_state(0, 0.), &mut t, &mut v);
+        assert_eq!(t.translation, Vec3::X * 4.5);
+    }
+
+    fn receive_within<T: DeserializeOwned>(
+        app: &mut App,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+        accept: impl Fn(&T) -> bool,
+    ) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            app.update();
+            while let Some((_, message)) = receive::<T>(socket, buf) {
+                if accept(&message) {
+                    return message;
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no answer from the server");
+    }
+
+    #[test]
+    fn loopback_join_input_state() {
+        let server = NetServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut app = App::new();
+        app.insert_resource(Config {
+            cars_count: 2,
+            ..default()
+        })
+        .init_resource::<Time>()
+        .insert_resource(server)
+        .add_system(net_server_receive_system)
+        .add_system(net_server_send_system.after(net_server_receive_system));
+        for id in 0..2 {
+            app.world
+                .spawn()
+                .insert(CarId(id))
+                .insert(Car::new(&[], true, 0., Transform::default()))
+                .insert(Transform::from_xyz(id as f32, 0., 0.))
+                .insert(Velocity::zero())
+                .insert(CarProgress::default());
+        }
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(addr).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut buf = vec![0; PACKET_SIZE];
+
+        send(&socket, None, &ClientMessage::Join);
+        let car = match receive_within(&mut app, &socket, &mut buf, |m| {
+            matches!(m, ServerMessage::Welcome { .. })
+        }) {
+            ServerMessage::Welcome { car } => car,
+            _ => unreachable!(),
+        };
+        assert_eq!(car, 0);
+
+        let input = NetInput {
+            seq: 7,
+            gas: 1.,
+            brake: 0.,
+            steering: -0.5,
+        };
+        send(&socket, None, &ClientMessage::Input(input));
+        let state = receive_within(&mut app, &socket, &mut buf, |m| {
+            matches!(m, ServerMessage::State { ack: 7, .. })
+        });
+        match state {
+            ServerMessage::State { cars, .. } => {
+                assert_eq!(cars.len(), 2);
+                assert_eq!(cars[1].translation, [1., 0., 0.]);
+            }
+            _ => unreachable!(),
+        }
+        let mut q_cars = app.world.query::<(&CarId, &Car)>();
+        let (_, driven) = q_cars.iter(&app.world).find(|(id, _)| id.0 == car).unwrap();
+        assert!(!driven.use_brain);
+        assert_eq!(driven.gas, 1.);
+        assert_eq!(driven.steering, -0.5);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::rng::*;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseConfig {
+    pub gaussian_std: f32,
+    pub dropout: f32,
+    pub quantization: f32,
+    pub delay_ticks: usize,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            gaussian_std: 0.,
+            dropout: 0.,
+            quantization: 0.,
+            delay_ticks: 0,
+        }
+    }
+}
+
+impl NoiseConfig {
+    pub fn apply(&self, rng: &mut SimRng, delay: &mut DelayLine, value: f32) -> f32 {
+        let mut v = value;
+        if self.dropout > 0. && rng.rng.gen::<f32>() < self.dropout {
+            v = 0.;
+        } else {
+            v += gaussian(&mut rng.rng, self.gaussian_std);
+        }
+        if self.quantization > 0. {
+            v = (v / self.quantization).round() * self.quantization;
+        }
+        delay.push(v, self.delay_ticks)
+    }
+}
+
+// a noise file may leave out the channels it keeps clean
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Noise {
+    pub sensor: NoiseConfig,
+    // by ray index, replaces sensor for that ray on every channel it feeds
+    pub rays: BTreeMap<usize, NoiseConfig>,
+    pub gas: NoiseConfig,
+    pub brake: NoiseConfig,
+    pub steering: NoiseConfig,
+}
+
+impl Noise {
+    pub fn load(path: &Path) -> Result<Noise, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("unable to open noise {}: {}", path.display(), e))?;
+        serde_json::from_reader(file)
+            .map_err(|e| format!("unable to parse noise {}: {}", path.display(), e))
+    }
+    pub fn ray(&self, ray: usize) -> &NoiseConfig {
+        self.rays.get(&ray).unwrap_or(&self.sensor)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DelayLine {
+    buf: VecDeque<f32>,
+}
+
+impl DelayLine {
+    pub fn push(&mut self, value: f32, ticks: usize) -> f32 {
+        self.buf.push_back(value);
+        if self.buf.len() > ticks {
+            while self.buf.len() > ticks + 1 {
+                self.buf.pop_front();
+            }
+            return self.buf.pop_front().unwrap();
+        }
+        0.
+    }
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct CarNoise {
+    pub sensors: Vec<DelayLine>,
+    pub gas: DelayLine,
+    pub brake: DelayLine,
+    pub steering: DelayLine,
+}
+
+impl CarNoise {
+    pub fn new(n_sensors: usize) -> Self {
+        Self {
+            sensors: vec![DelayLine::default(); n_sensors],
+            gas: DelayLine::default(),
+            brake: DelayLine::default(),
+            steering: DelayLine::default(),
+        }
+    }
+    pub fn clear(&mut self) {
+        for line in self.sensors.iter_mut() {
+            line.clear();
+        }
+        self.gas.clear();
+        self.brake.clear();
+        self.steering.clear();
+    }
+    // inputs hold one block of sensor_count values per channel
+    pub fn sensors(
+        &mut self,
+        noise: &Noise,
+        rng: &mut SimRng,
+        sensor_count: usize,
+        inputs: &mut [f32],
+    ) {
+        for (i, input) in inputs.iter_mut().enumerate() {
+            let v = noise
+                .ray(i % sensor_count.max(1))
+                .apply(rng, &mut self.sensors[i], *input);
+            *input = v.clamp(0., 1.);
+        }
+    }
+    pub fn actuators(
+        &mut self,
+        noise: &Noise,
+        rng: &mut SimRng,
+        gas: f32,
+        brake: f32,
+        steering: f32,
+    ) -> (f32, f32, f32) {
+        (
+            noise.gas.apply(rng, &mut self.gas, gas).clamp(0., 1.),
+            noise.brake.apply(rng, &mut self.brake, brake).clamp(0., 1.),
+            noise
+                .steering
+                .apply(rng, &mut self.steering, steering)
+                .clamp(-1., 1.),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_line_holds_values_for_the_given_ticks() {
+        let mut line = DelayLine::default();
+        let out: Vec<f32> = [1., 2., 3., 4.].iter().map(|v| line.push(*v, 2)).collect();
+        assert_eq!(out, vec![0., 0., 1., 2.]);
+        assert_eq!(DelayLine::default().push(5., 0), 5.);
+    }
+
+    #[test]
+    fn delay_line_shrinks_when_the_delay_drops() {
+        let mut line = DelayLine::default();
+        for v in [1., 2., 3.] {
+            line.push(v, 3);
+        }
+        assert_eq!(line.push(4., 1), 3.);
+        assert_eq!(line.push(5., 1), 4.);
+    }
+
+    #[test]
+    fn quantization_rounds_to_the_step() {
+        let config = NoiseConfig {
+            quantization: 0.25,
+            ..default()
+        };
+        let mut rng = SimRng::new(1);
+        let mut line = DelayLine::default();
+        let out: Vec<f32> = [0.3, 0.38, -0.1, 1.]
+            .iter()
+            .map(|v| config.apply(&mut rng, &mut line, *v))
+            .collect();
+        assert_eq!(out, vec![0.25, 0.5, 0., 1.]);
+    }
+
+    #[test]
+    fn full_dropout_zeroes_every_value() {
+        let config = NoiseConfig {
+            dropout: 1.,
+            ..default()
+        };
+        let mut rng = SimRng::new(1);
+        let mut line = DelayLine::default();
+        assert_eq!(config.apply(&mut rng, &mut line, 0.7), 0.);
+    }
+    #[test]
+    fn a_noise_file_may_leave_out_channels() {
+        let noise: Noise = serde_json::from_str(r#"{"sensor": {"dropout": 0.1}}"#).unwrap();
+        assert_eq!(noise.sensor.dropout, 0.1);
+        assert_eq!(noise.sensor.delay_ticks, 0);
+        assert_eq!(noise.steering.gaussian_std, 0.);
+    }
+
+    #[test]
+    fn a_ray_override_replaces_the_sensor_noise_on_every_channel() {
+        let noise: Noise =
+            serde_json::from_str(r#"{"sensor": {"dropout": 1.0}, "rays": {"1": {}}}"#).unwrap();
+        assert_eq!(noise.ray(0).dropout, 1.);
+        assert_eq!(noise.ray(1).dropout, 0.);
+        let mut car_noise = CarNoise::new(6);
+        let mut inputs = [0.5; 6];
+        car_noise.sensors(&noise, &mut SimRng::new(1), 3, &mut inputs);
+        assert_eq!(inputs, [0., 0.5, 0., 0., 0.5, 0.]);
+    }
+}
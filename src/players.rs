@@ -0,0 +1,220 @@
+use crate::{
+    camera::CameraController, car::Car, config::Config, fitness::CarStats, input::Player,
+    progress::CarProgress, race::*,
+};
+use bevy::{core_pipeline::clear_color::ClearColorConfig, prelude::*, render::camera::Viewport};
+use bevy_rapier3d::prelude::Velocity;
+
+// which player's car a camera follows
+#[derive(Component)]
+pub struct PlayerCamera {
+    pub player: usize,
+}
+
+#[derive(Component)]
+pub struct PlayerHudText {
+    pub player: usize,
+}
+
+// position and size of a player's part of the window, fractions from the top left
+pub fn split_rect(player: usize, players: usize) -> (Vec2, Vec2) {
+    match players {
+        0 | 1 => (Vec2::ZERO, Vec2::ONE),
+        2 => (Vec2::new(0., 0.5 * player as f32), Vec2::new(1., 0.5)),
+        _ => (
+            Vec2::new(0.5 * (player % 2) as f32, 0.5 * (player / 2) as f32),
+            Vec2::splat(0.5),
+        ),
+    }
+}
+
+// the first camera comes from camera_start_system
+pub fn split_screen_start_system(mut commands: Commands, config: Res<Config>) {
+    if config.players < 2 {
+        return;
+    }
+    println!(
+        "{} players drive with arrows, WSAD, home/end/delete/page down and numpad 8456, gamepads replace the last ones",
+        config.players
+    );
+    for player in 1..config.players {
+        commands
+            .spawn_bundle(Camera3dBundle {
+                camera: Camera {
+                    priority: player as isize,
+                    ..default()
+                },
+                // the first camera clears the whole window
+                camera_3d: Camera3d {
+                    clear_color: ClearColorConfig::None,
+                    ..default()
+                },
+                transform: Transform::from_translation(config.translation + Vec3::Y * 15.)
+                    .looking_at(config.translation, Vec3::Y),
+                ..default()
+            })
+            .insert(CameraController::default())
+            .insert(PlayerCamera { player })
+            .insert(UiCameraConfig { show_ui: false });
+    }
+    // ui over the whole window, drawn after all the viewports
+    commands.spawn_bundle(Camera2dBundle {
+        camera: Camera {
+            priority: config.players as isize,
+            ..default()
+        },
+        camera_2d: Camera2d {
+            clear_color: ClearColorConfig::None,
+        },
+        ..default()
+    });
+}
+
+pub fn split_screen_viewport_system(
+    windows: Res<Windows>,
+    config: Res<Config>,
+    mut q_cameras: Query<(&PlayerCamera, &mut Camera)>,
+) {
+    if config.players < 2 {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = Vec2::new(
+        window.physical_width() as f32,
+        window.physical_height() as f32,
+    );
+    for (player_camera, mut camera) in q_cameras.iter_mut() {
+        let (position, size) = split_rect(player_camera.player, config.players);
+        let viewport = Viewport {
+            physical_position: (position * window_size).as_uvec2(),
+            physical_size: (size * window_size).as_uvec2().max(UVec2::ONE),
+            ..default()
+        };
+        let changed = camera.viewport.as_ref().is_none_or(|v| {
+            v.physical_position != viewport.physical_position
+                || v.physical_size != viewport.physical_size
+        });
+        if changed {
+            camera.viewport = Some(viewport);
+        }
+    }
+}
+
+pub fn player_hud_start_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    asset_server: Res<AssetServer>,
+) {
+    if config.players < 2 {
+        return;
+    }
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    for player in 0..config.players {
+        let (position, size) = split_rect(player, config.players);
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Percent(position.x * 100.),
+                        top: Val::Percent(position.y * 100.),
+                        ..default()
+                    },
+                    size: Size::new(Val::Percent(size.x * 100.), Val::Percent(size.y * 100.)),
+                    ..default()
+                },
+                color: UiColor(Color::NONE),
+                ..default()
+            })
+            .insert(Name::new(format!("Player {} hud", player + 1)))
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(TextBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                bottom: Val::Px(8.0),
+                                right: Val::Px(12.0),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        text: Text::from_section(
+                            "",
+                            TextStyle {
+                                font: medium.clone(),
+                                font_size: 18.0,
+                                color: Color::GOLD,
+                            },
+                        ),
+                        ..default()
+                    })
+                    .insert(PlayerHudText { player });
+            });
+    }
+}
+
+pub fn player_hud_system(
+    config: Res<Config>,
+    race: Res<Race>,
+    q_cars: Query<(Entity, &CarProgress), With<Car>>,
+    q_players: Query<(Entity, &Player, &Velocity, &CarStats)>,
+    mut q_text: Query<(&PlayerHudText, &mut Text)>,
+) {
+    if config.players < 2 {
+        return;
+    }
+    let standings = race_standings(
+        &config,
+        &race,
+        q_cars.iter().map(|(e, progress)| (e, progress.meters)),
+    );
+    for (e, player, velocity, stats) in q_players.iter() {
+        let position = standings.iter().position(|s| s.entity == e);
+        let laps = position.map_or(0, |i| standings[i].laps);
+        let lap_time = |t: Option<f32>| t.map_or("-".to_string(), |t| format!("{:.2}", t));
+        let value = format!(
+            "P{}  {:.0} km/h\npos {}/{}  lap {}\nlast {}  best {}",
+            player.index + 1,
+            velocity.linvel.length() * 3.6,
+            position.map_or(0, |i| i + 1),
+            standings.len(),
+            laps,
+            lap_time(stats.lap_times.last().cloned()),
+            lap_time(stats.best_lap()),
+        );
+        for (hud, mut text) in q_text.iter_mut() {
+            if hud.player == player.index {
+                text.sections[0].value = value.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_rect_tiles_the_window() {
+        assert_eq!(split_rect(0, 1), (Vec2::ZERO, Vec2::ONE));
+        assert_eq!(split_rect(1, 2), (Vec2::new(0., 0.5), Vec2::new(1., 0.5)));
+        for players in 1..=4 {
+            let area: f32 = (0..players)
+                .map(|p| {
+                    let (position, size) = split_rect(p, players);
+                    assert!(position.cmpge(Vec2::ZERO).all());
+                    assert!((position + size).cmple(Vec2::ONE).all());
+                    size.x * size.y
+                })
+                .sum();
+            // three players leave the bottom right quarter empty
+            let expected = if players == 3 { 0.75 } else { 1. };
+            assert_eq!(area, expected);
+        }
+        assert_eq!(split_rect(3, 4).0, Vec2::new(0.5, 0.5));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::{car::RemoteCar, config::Config, track::*};
 use bevy::prelude::*;
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use obj::*;
@@ -7,20 +7,44 @@ use parry3d::shape::{Polyline, SegmentPointLocation};
 use std::fs::File;
 use std::io::BufReader;
 
-#[derive(Component)]
+// farther than this between two frames is a reset, not driving
+const TELEPORT_DISTANCE: f32 = 20.;
+
+#[derive(Component, Default)]
 pub struct CarProgress {
+    // from the start line, keeps growing over laps, negative behind the line
     pub meters: f32,
+    pub offset: f32,
+    // where meters was last measured, to tell driving from resets
+    pub last: Option<Vec3>,
+}
+
+// lap_meters is in [0, total), continued from the previous meters across the start line
+pub fn unwrap_meters(previous: Option<f32>, lap_meters: f32, total: f32) -> f32 {
+    match previous {
+        Some(previous) => lap_meters + ((previous - lap_meters) / total).round() * total,
+        // the grid is behind the line
+        None if lap_meters > total / 2. => lap_meters - total,
+        None => lap_meters,
+    }
 }
 
 pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Config>) {
+    spawn_track_polyline(&mut commands, &mut config, &TrackVariant::default());
+}
+
+pub fn spawn_track_polyline(commands: &mut Commands, config: &mut Config, variant: &TrackVariant) {
     let obj_path = "assets/track-polyline.obj";
     let polyline_buf = BufReader::new(File::open(obj_path).unwrap());
     let model = raw::parse_obj(polyline_buf).unwrap();
-    let vertices: Vec<Point3<Real>> = model
+    let mut vertices: Vec<Point3<Real>> = model
         .positions
         .iter()
-        .map(|pos| Point3::new(pos.0, pos.1, pos.2))
+        .map(|pos| Point3::from(variant.point([pos.0, pos.1, pos.2])))
         .collect();
+    if variant.reverse {
+        vertices.reverse();
+    }
 
     let polyline = Polyline::new(vertices.clone(), None);
     let initial_point = Point3::from(config.translation);
@@ -40,6 +64,7 @@ pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Co
     }
 
     let mut meters = 0.;
+    config.meters.clear();
     for s in polyline.segments() {
         config.meters.push(meters);
         meters += s.length();
@@ -53,6 +78,7 @@ pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Co
     commands
         .spawn()
         .insert(Name::new("Track polyline"))
+        .insert(TrackPart)
         .insert(collider)
         .insert(RigidBody::Fixed)
         .insert(Sensor)
@@ -61,15 +87,33 @@ pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Co
         )));
 }
 
+// point on the centerline, meters from the polyline start
+pub fn polyline_point(config: &Config, meters: f32) -> Option<(Vec3, Vec3)> {
+    let polyline = config.polyline.as_ref()?;
+    let meters = meters.rem_euclid(config.meters_total.max(1.));
+    polyline.segments().enumerate().find_map(|(i, segment)| {
+        let length = segment.length();
+        if meters > config.meters[i] + length {
+            return None;
+        }
+        let (a, b) = (segment.a, segment.b);
+        let (a, b) = (Vec3::new(a.x, a.y, a.z), Vec3::new(b.x, b.y, b.z));
+        let t = (meters - config.meters[i]) / length.max(f32::EPSILON);
+        Some((a.lerp(b, t), (b - a).normalize_or_zero()))
+    })
+}
+
 pub fn progress_system(
     config: Res<Config>,
-    mut cars: Query<(&Transform, &mut CarProgress), With<CarProgress>>,
+    mut cars: Query<(&Transform, &mut CarProgress), Without<RemoteCar>>,
 ) {
     if let Some(polyline) = &config.polyline {
         for (transform, mut car_progress) in cars.iter_mut() {
             let tr = transform.translation;
             let point: Point3<Real> = Point3::new(tr.x, tr.y, tr.z);
             let point_location = polyline.project_local_point_and_get_location(&point, true);
+            let projected = point_location.0.point;
+            car_progress.offset = Vec2::new(tr.x - projected.x, tr.z - projected.z).length();
             let (segment_i, segment_location) = point_location.1;
             let segment = polyline.segment(segment_i);
             match segment_location {
@@ -78,23 +122,42 @@ pub fn progress_system(
                 }
                 SegmentPointLocation::OnEdge(uv) => {
                     let m = uv[1] * segment.length();
-                    let meters = match segment_i {
-                        i if i >= config.segment_i => {
-                            m + config.meters[segment_i as usize] - config.meters_shift
-                        }
-                        _ => {
-                            m + config.meters[segment_i as usize] + config.meters_total
-                                - config.meters_shift
-                        }
-                    };
-                    if meters - car_progress.meters > 1000. {
-                        // println!("car antiprogress {:.1}", car_progress.meters);
-                        car_progress.meters = -(config.meters_total - meters);
-                    } else {
-                        car_progress.meters = meters;
-                    }
+                    let lap_meters = (m + config.meters[segment_i as usize] - config.meters_shift)
+                        .rem_euclid(config.meters_total);
+                    let previous = car_progress
+                        .last
+                        .filter(|last| last.distance(tr) < TELEPORT_DISTANCE)
+                        .map(|_| car_progress.meters);
+                    car_progress.meters = unwrap_meters(previous, lap_meters, config.meters_total);
+                    car_progress.last = Some(tr);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwrap_meters_counts_laps_across_the_line() {
+        let total = 1000.;
+        assert_eq!(unwrap_meters(Some(995.), 3., total), 1003.);
+        assert_eq!(unwrap_meters(Some(1995.), 2., total), 2002.);
+        assert_eq!(unwrap_meters(Some(500.), 510., total), 510.);
+    }
+
+    #[test]
+    fn unwrap_meters_goes_negative_backwards_over_the_line() {
+        let total = 1000.;
+        assert_eq!(unwrap_meters(Some(2.), 996., total), -4.);
+        assert_eq!(unwrap_meters(Some(1002.), 998., total), 998.);
+    }
+
+    #[test]
+    fn unwrap_meters_starts_behind_the_line_on_the_grid() {
+        assert_eq!(unwrap_meters(None, 990., 1000.), -10.);
+        assert_eq!(unwrap_meters(None, 10., 1000.), 10.);
+    }
+}
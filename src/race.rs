@@ -0,0 +1,225 @@
+use crate::{car::*, config::Config, progress::CarProgress};
+use bevy::prelude::*;
+use std::cmp::Ordering;
+
+pub struct Race {
+    pub laps: usize,
+    pub countdown: f64,
+    pub start_at: f64,
+    pub finished: Vec<(Entity, f64)>,
+}
+
+impl Default for Race {
+    fn default() -> Self {
+        Self {
+            laps: 3,
+            countdown: 5.,
+            start_at: 0.,
+            finished: vec![],
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct RaceText;
+
+#[derive(Debug, Clone)]
+pub struct RaceStanding {
+    pub entity: Entity,
+    pub laps: usize,
+    pub meters: f32,
+    pub finished_at: Option<f64>,
+}
+
+pub fn grid_offset(i: usize) -> Vec3 {
+    let row = (i / 2) as f32;
+    let side = (i % 2) as f32;
+    Vec3::new(2.5 - side * 5., 0., -8. * row - 4. * side)
+}
+
+pub fn laps(config: &Config, meters: f32) -> usize {
+    if config.meters_total <= 0. {
+        return 0;
+    }
+    (meters / config.meters_total).floor().max(0.) as usize
+}
+
+pub fn race_standings(
+    config: &Config,
+    race: &Race,
+    cars: impl Iterator<Item = (Entity, f32)>,
+) -> Vec<RaceStanding> {
+    let mut standings: Vec<RaceStanding> = cars
+        .map(|(entity, meters)| RaceStanding {
+            entity,
+            laps: laps(config, meters),
+            meters,
+            finished_at: race
+                .finished
+                .iter()
+                .find(|(e, _)| *e == entity)
+                .map(|(_, t)| *t),
+        })
+        .collect();
+    standings.sort_by(|a, b| match (a.finished_at, b.finished_at) {
+        (Some(ta), Some(tb)) => ta.partial_cmp(&tb).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => b
+            .laps
+            .cmp(&a.laps)
+            .then(b.meters.partial_cmp(&a.meters).unwrap_or(Ordering::Equal)),
+    });
+    standings
+}
+
+// cars wait on the grid until the countdown ends
+pub fn race_begin(config: &mut Config, race: &mut Race, seconds: f64) {
+    race.start_at = seconds + race.countdown;
+    config.use_brain = false;
+    config.reset_pause_until = race.start_at;
+}
+
+pub fn race_start_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut config: ResMut<Config>,
+    mut race: ResMut<Race>,
+    time: Res<Time>,
+) {
+    if !config.race_mode {
+        return;
+    }
+    race_begin(&mut config, &mut race, time.seconds_since_startup());
+
+    let bold: Handle<Font> = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(2.0),
+                    right: Val::Px(15.0),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "race: ".to_string(),
+                        style: TextStyle {
+                            font: bold.clone(),
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                        },
+                    },
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle {
+                            font: medium.clone(),
+                            font_size: 16.0,
+                            color: Color::GOLD,
+                        },
+                    },
+                ],
+                ..default()
+            },
+            ..default()
+        })
+        .insert(RaceText);
+}
+
+pub fn race_system(
+    config: Res<Config>,
+    mut race: ResMut<Race>,
+    time: Res<Time>,
+    q_cars: Query<(Entity, &CarProgress, &Name), With<Car>>,
+    mut q_text: Query<&mut Text, With<RaceText>>,
+) {
+    if !config.race_mode {
+        return;
+    }
+    let seconds = time.seconds_since_startup();
+    // the dedicated server has no dash
+    let mut text = q_text.get_single_mut().ok();
+    if seconds < race.start_at {
+        if let Some(ref mut text) = text {
+            text.sections[1].value = format!("{:.0}", (race.start_at - seconds).ceil());
+        }
+        return;
+    }
+    let race_seconds = seconds - race.start_at;
+    for (e, progress, name) in q_cars.iter() {
+        if race.finished.iter().any(|(f, _)| *f == e) {
+            continue;
+        }
+        if laps(&config, progress.meters) >= race.laps {
+            println!("{} finished {:.2}s", name, race_seconds);
+            race.finished.push((e, race_seconds));
+        }
+    }
+
+    let standings = race_standings(
+        &config,
+        &race,
+        q_cars.iter().map(|(e, progress, _)| (e, progress.meters)),
+    );
+    let mut text_string = format!("{:.1}s\n", race_seconds);
+    for (i, standing) in standings.iter().enumerate() {
+        let name = q_cars
+            .get(standing.entity)
+            .map_or("", |(_, _, n)| n.as_str());
+        let status = match standing.finished_at {
+            Some(t) => format!("{:.2}s", t),
+            None => format!("lap {}/{}", (standing.laps + 1).min(race.laps), race.laps),
+        };
+        text_string = text_string + &format!("P{} {} {}\n", i + 1, name, status);
+    }
+    if let Some(ref mut text) = text {
+        text.sections[1].value = text_string;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            meters_total: 1000.,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn laps_count_whole_laps_only() {
+        let config = config();
+        assert_eq!(laps(&config, -5.), 0);
+        assert_eq!(laps(&config, 999.), 0);
+        assert_eq!(laps(&config, 2100.), 2);
+        assert_eq!(laps(&Config::default(), 2100.), 0);
+    }
+
+    #[test]
+    fn standings_rank_finishers_then_laps_then_meters() {
+        let (a, b, c, d) = (
+            Entity::from_raw(0),
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+        let race = Race {
+            finished: vec![(c, 90.), (b, 80.)],
+            ..default()
+        };
+        let cars = [(a, 1500.), (b, 3010.), (c, 3005.), (d, 2100.)];
+        let standings = race_standings(&config(), &race, cars.into_iter());
+        let order: Vec<Entity> = standings.iter().map(|s| s.entity).collect();
+        assert_eq!(order, vec![b, c, d, a]);
+        assert_eq!(standings[2].laps, 2);
+        assert_eq!(standings[0].finished_at, Some(80.));
+    }
+}
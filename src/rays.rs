@@ -0,0 +1,144 @@
+use crate::config::Config;
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues, render_resource::PrimitiveTopology, view::NoFrustumCulling,
+    },
+};
+
+// segments car_brain_system cast this frame, with the color they are drawn in
+#[derive(Default)]
+pub struct RayLines {
+    pub segments: Vec<(Vec3, Vec3, Color)>,
+}
+
+// red when the wall is close, green when far, faint when nothing was hit
+fn ray_color(distance: f32, hit: bool, max_toi: f32) -> Color {
+    match hit {
+        true => {
+            let t = (distance / max_toi).clamp(0., 1.);
+            Color::rgba(1. - t, t, 0.2, 0.9)
+        }
+        false => Color::rgba(0.25, 0.88, 0.82, 0.15),
+    }
+}
+
+impl RayLines {
+    pub fn push_ray(&mut self, origin: Vec3, end: Vec3, hit: bool, max_toi: f32) {
+        let color = ray_color(origin.distance(end), hit, max_toi);
+        self.segments.push((origin, end, color));
+    }
+}
+
+#[derive(Component)]
+pub struct RayLinesMesh;
+
+fn line_list(segments: &[(Vec3, Vec3, Color)]) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = Vec::with_capacity(segments.len() * 2);
+    let mut colors: Vec<[f32; 4]> = Vec::with_capacity(segments.len() * 2);
+    for (a, b, color) in segments {
+        positions.push(a.to_array());
+        positions.push(b.to_array());
+        colors.push(color.as_rgba_f32());
+        colors.push(color.as_rgba_f32());
+    }
+    // the pbr pipeline wants these even for lines
+    let normals: Vec<[f32; 3]> = positions.iter().map(|_| [0., 1., 0.]).collect();
+    let uvs: Vec<[f32; 2]> = positions.iter().map(|_| [0., 0.]).collect();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        VertexAttributeValues::from(positions),
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, VertexAttributeValues::from(colors));
+    mesh
+}
+
+pub fn rays_start_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(line_list(&[])),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Name::new("Rays"))
+        .insert(RayLinesMesh)
+        // vertices move every frame, the aabb from the first one would cull them
+        .insert(NoFrustumCulling);
+}
+
+// R cycles rays off, followed car, every car
+pub fn rays_key_system(keys: Res<Input<KeyCode>>, mut config: ResMut<Config>) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    let (show, all) = match (config.show_rays, config.rays_all_cars) {
+        (false, _) => (true, false),
+        (true, false) => (true, true),
+        (true, true) => (false, false),
+    };
+    config.show_rays = show;
+    config.rays_all_cars = all;
+}
+
+pub fn rays_system(
+    config: Res<Config>,
+    ray_lines: Res<RayLines>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut q_lines: Query<(&Handle<Mesh>, &mut Visibility), With<RayLinesMesh>>,
+) {
+    let (handle, mut visibility) = q_lines.single_mut();
+    let visible = config.show_rays && !ray_lines.segments.is_empty();
+    if visibility.is_visible != visible {
+        visibility.is_visible = visible;
+    }
+    if visible {
+        if let Some(mesh) = meshes.get_mut(handle) {
+            *mesh = line_list(&ray_lines.segments);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_close_wall_is_red_and_a_far_one_green() {
+        assert_eq!(ray_color(0., true, 10.), Color::rgba(1., 0., 0.2, 0.9));
+        assert_eq!(ray_color(10., true, 10.), Color::rgba(0., 1., 0.2, 0.9));
+        let mid = ray_color(5., true, 10.);
+        assert_eq!((mid.r(), mid.g()), (0.5, 0.5));
+        // past max_toi stays green
+        assert_eq!(ray_color(25., true, 10.), ray_color(10., true, 10.));
+    }
+
+    #[test]
+    fn a_miss_is_faint_whatever_its_length() {
+        let miss = ray_color(3., false, 10.);
+        assert_eq!(miss, ray_color(10., false, 10.));
+        assert!(miss.a() < ray_color(10., true, 10.).a());
+    }
+
+    #[test]
+    fn each_ray_is_one_segment_of_the_line_list() {
+        let mut lines = RayLines::default();
+        lines.push_ray(Vec3::ZERO, Vec3::Z * 2., true, 10.);
+        lines.push_ray(Vec3::ZERO, Vec3::X * 10., false, 10.);
+        assert_eq!(lines.segments[0].2, ray_color(2., true, 10.));
+        let mesh = line_list(&lines.segments);
+        assert_eq!(mesh.count_vertices(), 4);
+    }
+}
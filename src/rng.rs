@@ -0,0 +1,47 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RngState {
+    pub seed: u64,
+    pub stream: u64,
+    pub word_pos: u128,
+}
+
+pub struct SimRng {
+    pub seed: u64,
+    pub rng: ChaCha8Rng,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+    pub fn state(&self) -> RngState {
+        RngState {
+            seed: self.seed,
+            stream: self.rng.get_stream(),
+            word_pos: self.rng.get_word_pos(),
+        }
+    }
+    pub fn from_state(state: &RngState) -> Self {
+        let mut sim_rng = SimRng::new(state.seed);
+        sim_rng.rng.set_stream(state.stream);
+        sim_rng.rng.set_word_pos(state.word_pos);
+        sim_rng
+    }
+}
+
+// Box-Muller, rand_distr is not worth a dependency for one distribution
+pub fn gaussian<R: Rng>(rng: &mut R, std_dev: f32) -> f32 {
+    if std_dev <= 0. {
+        return 0.;
+    }
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.);
+    let u2: f32 = rng.gen();
+    std_dev * (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+}
@@ -0,0 +1,400 @@
+use crate::{car::*, chart::*, config::Config};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// wheels are in spawn order: front right, front left, back right, back left
+pub const CHANNELS: [(&str, Color); 10] = [
+    ("kmh", Color::WHITE),
+    ("gas", Color::GREEN),
+    ("brake", Color::RED),
+    ("steering", Color::YELLOW),
+    ("slip_fr", Color::CYAN),
+    ("slip_fl", Color::TURQUOISE),
+    ("slip_br", Color::VIOLET),
+    ("slip_bl", Color::PINK),
+    ("lateral_g", Color::ORANGE),
+    ("yaw_dps", Color::LIME_GREEN),
+];
+const GRAVITY: f32 = 9.81;
+const CHART: Vec2 = Vec2::new(360., 150.);
+const BUTTONS_WIDTH: f32 = 100.;
+const BUTTON_HEIGHT: f32 = 13.;
+const SEGMENTS: usize = 120;
+
+pub struct TelemetrySample {
+    pub seconds: f64,
+    pub values: [f32; CHANNELS.len()],
+}
+
+// rolling window of the followed car, restarted when the camera switches cars
+pub struct Telemetry {
+    pub window: f64,
+    pub samples: VecDeque<TelemetrySample>,
+    pub enabled: [bool; CHANNELS.len()],
+    pub car: Option<Entity>,
+    pub path: PathBuf,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        let mut enabled = [false; CHANNELS.len()];
+        enabled[..4].fill(true);
+        Self {
+            window: 10.,
+            samples: VecDeque::new(),
+            enabled,
+            car: None,
+            path: Path::new("metrics").join("telemetry.csv"),
+        }
+    }
+}
+
+impl Telemetry {
+    pub fn series(&self, channel: usize) -> Vec<f32> {
+        self.samples.iter().map(|s| s.values[channel]).collect()
+    }
+
+    // keeps the last window seconds
+    fn push(&mut self, sample: TelemetrySample) {
+        let seconds = sample.seconds;
+        self.samples.push_back(sample);
+        while let Some(sample) = self.samples.front() {
+            if seconds - sample.seconds <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    // only the enabled channels, as they are on screen
+    fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let channels: Vec<usize> = (0..CHANNELS.len()).filter(|i| self.enabled[*i]).collect();
+        let header: Vec<&str> = channels.iter().map(|i| CHANNELS[*i].0).collect();
+        writeln!(out, "seconds,{}", header.join(","))?;
+        for sample in self.samples.iter() {
+            let values: Vec<String> = channels
+                .iter()
+                .map(|i| format!("{:.4}", sample.values[*i]))
+                .collect();
+            writeln!(out, "{:.4},{}", sample.seconds, values.join(","))?;
+        }
+        Ok(())
+    }
+
+    pub fn export(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        self.write_csv(&mut File::create(&self.path)?)
+    }
+}
+
+// centripetal, steadier than differentiating the solver velocity
+fn lateral_g(velocity: &Velocity, rotation: Quat) -> f32 {
+    let forward = rotation.mul_vec3(Vec3::Z);
+    velocity.angvel.y * velocity.linvel.dot(forward) / GRAVITY
+}
+
+// one value per channel, slips in wheel spawn order
+fn channel_values(
+    car: &Car,
+    velocity: &Velocity,
+    rotation: Quat,
+    slips: [f32; 4],
+) -> [f32; CHANNELS.len()] {
+    let mut values = [0.; CHANNELS.len()];
+    values[0] = velocity.linvel.length() * 3.6;
+    values[1] = car.gas;
+    values[2] = car.brake;
+    values[3] = car.steering;
+    values[4..8].copy_from_slice(&slips);
+    values[8] = lateral_g(velocity, rotation);
+    values[9] = velocity.angvel.y.to_degrees();
+    values
+}
+
+#[derive(Component)]
+pub struct TelemetryPanel;
+
+#[derive(Component)]
+pub struct TelemetryChart;
+
+// a channel toggle, or the csv export when channel is None
+#[derive(Component)]
+pub struct TelemetryButton {
+    pub channel: Option<usize>,
+}
+
+#[derive(Component)]
+pub struct TelemetryButtonText {
+    pub channel: Option<usize>,
+}
+
+pub fn telemetry_start_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    let chart = spawn_chart(
+        &mut commands,
+        medium.clone(),
+        UiRect {
+            top: Val::Px(0.),
+            left: Val::Px(BUTTONS_WIDTH),
+            ..default()
+        },
+        CHART,
+        &CHANNELS,
+        SEGMENTS,
+    );
+    commands.entity(chart).insert(TelemetryChart);
+    let buttons = CHANNELS
+        .iter()
+        .enumerate()
+        .map(|(i, _)| Some(i))
+        .chain([None]);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(100.0),
+                    right: Val::Px(330.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(BUTTONS_WIDTH + CHART.x), Val::Px(CHART.y)),
+                flex_direction: FlexDirection::ColumnReverse,
+                display: Display::None,
+                ..default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
+            ..default()
+        })
+        .insert(Name::new("Telemetry"))
+        .insert(TelemetryPanel)
+        .add_child(chart)
+        .with_children(|parent| {
+            for channel in buttons {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(BUTTONS_WIDTH), Val::Px(BUTTON_HEIGHT)),
+                            padding: UiRect {
+                                left: Val::Px(4.),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        color: UiColor(Color::NONE),
+                        ..default()
+                    })
+                    .insert(TelemetryButton { channel })
+                    .with_children(|button| {
+                        button
+                            .spawn_bundle(TextBundle {
+                                text: Text::from_section(
+                                    "",
+                                    TextStyle {
+                                        font: medium.clone(),
+                                        font_size: 11.0,
+                                        color: Color::WHITE,
+                                    },
+                                ),
+                                ..default()
+                            })
+                            .insert(TelemetryButtonText { channel });
+                    });
+            }
+        });
+}
+
+pub fn telemetry_record_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut telemetry: ResMut<Telemetry>,
+    q_cars: Query<(&Car, &Velocity, &Transform)>,
+    q_wheels: Query<&Wheel>,
+) {
+    let followed = config.camera_follow.or(config.hid_car);
+    if followed != telemetry.car {
+        telemetry.car = followed;
+        telemetry.samples.clear();
+    }
+    let (car, velocity, transform) = match followed.and_then(|e| q_cars.get(e).ok()) {
+        Some(car) => car,
+        None => return,
+    };
+    let mut slips = [0.; 4];
+    for (i, wheel_e) in car.wheels.iter().take(4).enumerate() {
+        if let Ok(wheel) = q_wheels.get(*wheel_e) {
+            slips[i] = wheel.slip;
+        }
+    }
+    telemetry.push(TelemetrySample {
+        seconds: time.seconds_since_startup(),
+        values: channel_values(car, velocity, transform.rotation, slips),
+    });
+}
+
+pub fn telemetry_click_system(
+    mut telemetry: ResMut<Telemetry>,
+    q_buttons: Query<(&Interaction, &TelemetryButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in q_buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button.channel {
+            Some(channel) => telemetry.enabled[channel] = !telemetry.enabled[channel],
+            None => match telemetry.export() {
+                Ok(()) => println!("telemetry saved to {}", telemetry.path.display()),
+                Err(e) => println!("unable to write {}: {}", telemetry.path.display(), e),
+            },
+        }
+    }
+}
+
+// V shows the panel
+pub fn telemetry_system(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    telemetry: Res<Telemetry>,
+    mut q_panel: Query<&mut Style, (With<TelemetryPanel>, Without<ChartSegment>)>,
+    q_chart: Query<(Entity, &Chart, &Node, &GlobalTransform), With<TelemetryChart>>,
+    mut q_segments: Query<(&ChartSegment, &mut Style, &mut Transform)>,
+    mut q_label: Query<(&ChartLabel, &mut Text)>,
+    mut q_buttons: Query<(&TelemetryButtonText, &mut Text), Without<ChartLabel>>,
+) {
+    let mut style = q_panel.single_mut();
+    if keys.just_pressed(KeyCode::V) {
+        style.display = match style.display {
+            Display::None => Display::Flex,
+            Display::Flex => Display::None,
+        };
+    }
+    if style.display == Display::None {
+        return;
+    }
+
+    // every channel on its own scale, hidden ones without segments
+    let (chart_e, chart, node, transform) = q_chart.single();
+    let points: Vec<Vec<Vec2>> = (0..CHANNELS.len())
+        .map(|i| match telemetry.enabled[i] {
+            true => chart_points(chart, &[telemetry.series(i)]).remove(0),
+            false => vec![],
+        })
+        .collect();
+    draw_chart(chart_e, &points, &mut q_segments);
+
+    let n = telemetry.samples.len();
+    let hovered = chart_hover(&windows, chart, node, transform, n);
+    let sample = match hovered.or_else(|| n.checked_sub(1)) {
+        Some(i) => &telemetry.samples[i],
+        None => return,
+    };
+    let newest = telemetry.samples.back().map_or(0., |s| s.seconds);
+    for (label, mut text) in q_label.iter_mut() {
+        if label.chart == chart_e {
+            text.sections[0].value = format!("t {:+.2}s", sample.seconds - newest);
+        }
+    }
+    for (button, mut text) in q_buttons.iter_mut() {
+        let section = &mut text.sections[0];
+        match button.channel {
+            Some(i) => {
+                let (name, color) = CHANNELS[i];
+                section.value = format!("{:<9} {:>6.2}", name, sample.values[i]);
+                section.style.color = match telemetry.enabled[i] {
+                    true => color,
+                    false => Color::DARK_GRAY,
+                };
+            }
+            None => section.value = "export csv".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seconds: f64, kmh: f32) -> TelemetrySample {
+        let mut values = [0.; CHANNELS.len()];
+        values[0] = kmh;
+        values[2] = 0.5;
+        TelemetrySample { seconds, values }
+    }
+
+    #[test]
+    fn the_window_drops_old_samples() {
+        let mut telemetry = Telemetry::default();
+        for i in 0..25 {
+            telemetry.push(sample(i as f64, i as f32));
+        }
+        // 14 to 24 inclusive
+        assert_eq!(telemetry.samples.len(), 11);
+        assert_eq!(telemetry.series(0)[0], 14.);
+    }
+
+    #[test]
+    fn the_csv_has_the_enabled_channels() {
+        let mut telemetry = Telemetry {
+            enabled: [false; CHANNELS.len()],
+            ..default()
+        };
+        telemetry.enabled[0] = true;
+        telemetry.enabled[2] = true;
+        telemetry.push(sample(1., 36.));
+        telemetry.push(sample(1.5, 72.25));
+        let mut out = vec![];
+        telemetry.write_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "seconds,kmh,brake\n1.0000,36.0000,0.5000\n1.5000,72.2500,0.5000\n"
+        );
+    }
+
+    #[test]
+    fn turning_at_speed_pulls_lateral_g() {
+        let velocity = Velocity {
+            linvel: Vec3::Z * 20.,
+            angvel: Vec3::Y * 0.4905,
+        };
+        assert!((lateral_g(&velocity, Quat::IDENTITY) - 1.).abs() < 1e-5);
+        // the sign follows the turn direction
+        let left = Velocity {
+            angvel: -velocity.angvel,
+            ..velocity
+        };
+        assert!((lateral_g(&left, Quat::IDENTITY) + 1.).abs() < 1e-5);
+        // reversing through the same turn pulls the other way
+        let heading_back = Quat::from_rotation_y(std::f32::consts::PI);
+        assert!((lateral_g(&velocity, heading_back) + 1.).abs() < 1e-5);
+        // sliding sideways without turning pulls nothing
+        let sliding = Velocity::linear(Vec3::X * 20.);
+        assert_eq!(lateral_g(&sliding, Quat::IDENTITY), 0.);
+    }
+
+    #[test]
+    fn channels_follow_the_names() {
+        let mut car = Car::new(&[], true, 1., Transform::default());
+        car.gas = 0.8;
+        car.steering = -0.25;
+        let velocity = Velocity {
+            linvel: Vec3::Z * 10.,
+            angvel: Vec3::Y * std::f32::consts::FRAC_PI_2,
+        };
+        let values = channel_values(&car, &velocity, Quat::IDENTITY, [0.1, 0.2, 0.3, 0.4]);
+        let value = |name: &str| values[CHANNELS.iter().position(|c| c.0 == name).unwrap()];
+        assert_eq!(value("kmh"), 36.);
+        assert_eq!(value("gas"), 0.8);
+        assert_eq!(value("brake"), 0.);
+        assert_eq!(value("steering"), -0.25);
+        assert_eq!(value("slip_fl"), 0.2);
+        assert_eq!(value("slip_bl"), 0.4);
+        assert!((value("yaw_dps") - 90.).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{
+    brain::CarBrain, car::Car, config::Config, contact::CarContacts, fitness::CarStats, progress::*,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, path::Path};
+
+// a rules file only needs the rules it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminationRules {
+    pub no_progress_seconds: f32,
+    pub no_progress_meters: f32,
+    pub wall_contact: bool,
+    pub wrong_way_meters: f32,
+    pub max_tilt: f32,
+}
+
+impl Default for TerminationRules {
+    fn default() -> Self {
+        Self {
+            no_progress_seconds: 3.,
+            no_progress_meters: 1.,
+            wall_contact: false,
+            wrong_way_meters: 10.,
+            max_tilt: std::f32::consts::FRAC_PI_3,
+        }
+    }
+}
+
+impl TerminationRules {
+    pub fn load(path: &Path) -> Result<TerminationRules, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("unable to open termination rules {}: {}", path.display(), e))?;
+        serde_json::from_reader(file).map_err(|e| {
+            format!(
+                "unable to parse termination rules {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    NoProgress,
+    WallContact,
+    WrongWay,
+    Flipped,
+    OutOfBounds,
+}
+
+impl Termination {
+    pub fn is_crash(&self) -> bool {
+        matches!(
+            self,
+            Termination::WallContact | Termination::Flipped | Termination::OutOfBounds
+        )
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct CarTermination {
+    pub done: Option<Termination>,
+    pub best_meters: f32,
+    pub best_meters_at: f32,
+}
+
+impl Default for CarTermination {
+    fn default() -> Self {
+        Self {
+            done: None,
+            // cars behind the start line have negative meters
+            best_meters: f32::MIN,
+            best_meters_at: 0.,
+        }
+    }
+}
+
+impl CarTermination {
+    pub fn clear(&mut self) {
+        *self = CarTermination::default();
+    }
+    // the best meters reached and when, the base of the wrong way and no progress rules
+    pub fn track(&mut self, rules: &TerminationRules, meters: f32, seconds: f32, lap: f32) {
+        // a drop of half a lap is a reset to the grid, not driving backwards
+        if meters < self.best_meters - lap / 2.
+            || meters > self.best_meters + rules.no_progress_meters
+        {
+            self.best_meters = meters;
+            self.best_meters_at = seconds;
+        }
+    }
+}
+
+pub fn check(
+    rules: &TerminationRules,
+    termination: &CarTermination,
+    tilt: f32,
+    wall_contacts: usize,
+    meters: f32,
+    seconds: f32,
+) -> Option<Termination> {
+    if tilt > rules.max_tilt {
+        Some(Termination::Flipped)
+    } else if rules.wall_contact && wall_contacts > 0 {
+        Some(Termination::WallContact)
+    } else if meters < termination.best_meters - rules.wrong_way_meters {
+        Some(Termination::WrongWay)
+    } else if seconds - termination.best_meters_at > rules.no_progress_seconds {
+        Some(Termination::NoProgress)
+    } else {
+        None
+    }
+}
+
+pub fn termination_system(
+    config: Res<Config>,
+    rules: Res<TerminationRules>,
+    mut q_cars: Query<
+        (
+            &Transform,
+            &mut Car,
+            &CarProgress,
+            &CarStats,
+            &CarContacts,
+            &mut CarTermination,
+            &Name,
+        ),
+        With<CarBrain>,
+    >,
+) {
+    if !config.use_brain || config.race_mode {
+        return;
+    }
+    for (t, mut car, progress, stats, contacts, mut termination, name) in q_cars.iter_mut() {
+        if termination.done.is_some() || !car.use_brain {
+            continue;
+        }
+        termination.track(&rules, progress.meters, stats.seconds, config.meters_total);
+        let tilt = t.rotation.mul_vec3(Vec3::Y).angle_between(Vec3::Y);
+        let done = check(
+            &rules,
+            &termination,
+            tilt,
+            contacts.walls,
+            progress.meters,
+            stats.seconds,
+        );
+        if let Some(reason) = done {
+            println!("{} done {:?} {:.1}", name, reason, progress.meters);
+            termination.done = Some(reason);
+            car.use_brain = false;
+            car.gas = 0.;
+            car.brake = 1.;
+            car.steering = 0.;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driving(meters: f32, seconds: f32) -> CarTermination {
+        CarTermination {
+            done: None,
+            best_meters: meters,
+            best_meters_at: seconds,
+        }
+    }
+
+    #[test]
+    fn a_tilted_car_is_flipped_before_anything_else() {
+        let rules = TerminationRules::default();
+        let termination = driving(100., 0.);
+        let tilt = rules.max_tilt + 0.1;
+        assert_eq!(
+            check(&rules, &termination, tilt, 1, 0., 10.),
+            Some(Termination::Flipped)
+        );
+        assert_eq!(check(&rules, &termination, 0.1, 0, 100., 1.), None);
+    }
+
+    #[test]
+    fn wall_contact_ends_the_run_only_when_enabled() {
+        let mut rules = TerminationRules::default();
+        let termination = driving(100., 0.);
+        assert_eq!(check(&rules, &termination, 0., 1, 100., 1.), None);
+        rules.wall_contact = true;
+        assert_eq!(
+            check(&rules, &termination, 0., 1, 100., 1.),
+            Some(Termination::WallContact)
+        );
+    }
+
+    #[test]
+    fn backing_off_the_best_meters_is_wrong_way() {
+        let rules = TerminationRules::default();
+        let termination = driving(100., 0.);
+        assert_eq!(check(&rules, &termination, 0., 0, 95., 1.), None);
+        assert_eq!(
+            check(&rules, &termination, 0., 0, 89., 1.),
+            Some(Termination::WrongWay)
+        );
+    }
+
+    #[test]
+    fn no_progress_counts_from_the_last_best_meters() {
+        let rules = TerminationRules::default();
+        let mut termination = driving(100., 0.);
+        // under no_progress_meters does not count as progress
+        termination.track(&rules, 100.5, 2., 1000.);
+        assert_eq!(termination.best_meters_at, 0.);
+        assert_eq!(
+            check(&rules, &termination, 0., 0, 100.5, 3.5),
+            Some(Termination::NoProgress)
+        );
+        termination.track(&rules, 102., 3., 1000.);
+        assert_eq!(check(&rules, &termination, 0., 0, 102., 3.5), None);
+    }
+
+    #[test]
+    fn a_reset_to_the_grid_is_not_wrong_way() {
+        let rules = TerminationRules::default();
+        let mut termination = driving(900., 10.);
+        termination.track(&rules, -5., 11., 1000.);
+        assert_eq!(termination.best_meters, -5.);
+        assert_eq!(check(&rules, &termination, 0., 0, -5., 11.), None);
+    }
+
+    #[test]
+    fn a_rules_file_keeps_the_defaults_it_leaves_out() {
+        let rules: TerminationRules = serde_json::from_str(r#"{"wall_contact": true}"#).unwrap();
+        assert!(rules.wall_contact);
+        assert_eq!(rules.no_progress_seconds, 3.);
+        assert_eq!(rules.wrong_way_meters, 10.);
+    }
+}
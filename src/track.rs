@@ -4,6 +4,7 @@ use bevy::render::mesh::{Indices, PrimitiveTopology};
 use bevy_rapier3d::na::Point3;
 use bevy_rapier3d::prelude::*;
 use bevy_rapier3d::rapier::prelude::ColliderShape;
+use serde::{Deserialize, Serialize};
 
 use std::f32::consts::PI;
 use std::fs::File;
@@ -11,10 +12,66 @@ use std::io::BufReader;
 
 pub const STATIC_GROUP: u32 = 0b010;
 
+#[derive(Component)]
+pub struct Wall;
+
+// despawned when the curriculum switches tracks
+#[derive(Component)]
+pub struct TrackPart;
+
+// procedural variant of the track in assets, the default is the track as modeled
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackVariant {
+    pub name: String,
+    pub mirror: bool,
+    pub reverse: bool,
+}
+
+impl TrackVariant {
+    pub fn new(name: &str, mirror: bool, reverse: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            mirror,
+            reverse,
+        }
+    }
+    pub fn point(&self, p: [f32; 3]) -> [f32; 3] {
+        match self.mirror {
+            true => [-p[0], p[1], p[2]],
+            false => p,
+        }
+    }
+    pub fn start(&self, translation: Vec3, quat: Quat) -> (Vec3, Quat) {
+        let (mut translation, mut quat) = (translation, quat);
+        if self.mirror {
+            translation.x = -translation.x;
+            quat = Quat::from_xyzw(quat.x, -quat.y, -quat.z, quat.w);
+        }
+        if self.reverse {
+            quat = quat.mul_quat(Quat::from_rotation_y(PI));
+        }
+        (translation, quat)
+    }
+}
+
 pub fn track_start_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    spawn_track(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &TrackVariant::default(),
+    );
+}
+
+pub fn spawn_track(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    variant: &TrackVariant,
 ) {
     let geoms = models();
     for obj_path in geoms.into_iter() {
@@ -27,17 +84,30 @@ pub fn track_start_system(
             .vertices
             .iter()
             .map(|v| {
-                [
+                variant.point([
                     v.position[0],
                     match is_road {
                         true => 0., // fix small deviations from 0. after blender obj triangulation export
                         false => v.position[1],
                     },
                     v.position[2],
-                ]
+                ])
+            })
+            .collect();
+        let normales: Vec<[f32; 3]> = obj
+            .vertices
+            .iter()
+            .map(|v| variant.point(v.normal))
+            .collect();
+        // mirroring flips the winding
+        let triangles: Vec<[u32; 3]> = obj
+            .indices
+            .chunks(3)
+            .map(|idx| match variant.mirror {
+                true => [idx[0], idx[2], idx[1]],
+                false => [idx[0], idx[1], idx[2]],
             })
             .collect();
-        let normales: Vec<[f32; 3]> = obj.vertices.iter().map(|v| v.normal).collect();
         let uv_data: Vec<[f32; 2]> = obj
             .vertices
             .iter()
@@ -49,7 +119,7 @@ pub fn track_start_system(
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normales);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uv_data);
         mesh.set_indices(Some(Indices::U32(
-            obj.indices.iter().map(|i| *i as u32).collect(),
+            triangles.iter().flatten().cloned().collect(),
         )));
 
         let vertices: Vec<Point3<Real>> = positions
@@ -57,13 +127,7 @@ pub fn track_start_system(
             .map(|v| Point3::new(v[0], v[1], v[2]))
             .collect();
 
-        let indices: Vec<_> = obj
-            .indices
-            .chunks(3)
-            .map(|idx| [idx[0] as u32, idx[1] as u32, idx[2] as u32])
-            .collect();
-
-        let collider = Collider::from(ColliderShape::trimesh(vertices, indices));
+        let collider = Collider::from(ColliderShape::trimesh(vertices, triangles));
 
         let pbr = PbrBundle {
             mesh: meshes.add(mesh),
@@ -83,10 +147,11 @@ pub fn track_start_system(
             true => 1.,
             false => 0.01,
         };
-        commands
+        let track = commands
             .spawn()
             .insert_bundle(pbr)
             .insert(Name::new("Track"))
+            .insert(TrackPart)
             .insert(collider)
             .insert(CollisionGroups::new(STATIC_GROUP, u32::MAX))
             .insert(RigidBody::Fixed)
@@ -95,7 +160,11 @@ pub fn track_start_system(
             .insert_bundle(TransformBundle::from_transform(Transform {
                 translation: Vec3::new(0., h, 0.),
                 ..default()
-            }));
+            }))
+            .id();
+        if !is_road {
+            commands.entity(track).insert(Wall);
+        }
     }
 }
 
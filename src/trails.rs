@@ -0,0 +1,494 @@
+use crate::{
+    car::Car,
+    config::Config,
+    curriculum::{active_track_name, Curriculum},
+    progress::CarProgress,
+    race::laps,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues, render_resource::PrimitiveTopology, view::NoFrustumCulling,
+    },
+};
+use bevy_rapier3d::prelude::Velocity;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    path::Path,
+};
+
+pub const RACING_LINE_FILE: &str = "racing_line.json";
+// car origin is about this high above the road
+const GROUND_OFFSET: f32 = 0.8;
+const TRAIL_WIDTH: f32 = 0.4;
+const RACING_LINE_WIDTH: f32 = 0.6;
+// a new point every meter driven
+const POINT_SPACING: f32 = 1.;
+// farther than this between two frames is a reset, not driving
+const TELEPORT_DISTANCE: f32 = 20.;
+const SPEED_MAX: f32 = 50.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailColor {
+    Speed,
+    Input,
+}
+
+pub struct Trails {
+    pub color: Option<TrailColor>,
+    pub seconds: f64,
+    pub racing_line: bool,
+}
+
+impl Default for Trails {
+    fn default() -> Self {
+        Self {
+            color: None,
+            seconds: 5.,
+            racing_line: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RacingLine {
+    pub lap_time: f32,
+    pub points: Vec<[f32; 3]>,
+}
+
+// best lap per track variant, the curriculum rotates through several
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RacingLines {
+    pub lines: BTreeMap<String, RacingLine>,
+    // track the racing line mesh was built for
+    #[serde(skip)]
+    pub shown: Option<String>,
+}
+
+impl RacingLines {
+    pub fn load(path: &Path) -> RacingLines {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return RacingLines::default(),
+        };
+        serde_json::from_reader(file).unwrap_or_else(|e| {
+            println!("unable to parse {}: {}", path.display(), e);
+            RacingLines::default()
+        })
+    }
+
+    // keeps the lap when it is the first or the fastest on that track
+    pub fn offer(&mut self, track_name: &str, lap_time: f32, points: &[Vec3]) -> bool {
+        let best = self.lines.get(track_name).map(|line| line.lap_time);
+        if best.is_some_and(|best| best <= lap_time) {
+            return false;
+        }
+        let line = RacingLine {
+            lap_time,
+            points: points.iter().map(|p| p.to_array()).collect(),
+        };
+        self.lines.insert(track_name.to_string(), line);
+        if self.shown.as_deref() == Some(track_name) {
+            self.shown = None;
+        }
+        true
+    }
+
+    pub fn save(&self, path: &Path) {
+        let saved = File::create(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::to_writer(file, self).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            println!("unable to save {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[derive(Component, Default)]
+pub struct CarTrail {
+    pub points: VecDeque<(f64, Vec3, Color)>,
+    // last recorded point, kept while the trail is hidden
+    pub last: Option<Vec3>,
+    pub lap: usize,
+    // None until the car crosses the line, so the standing start never counts, a reset drops the lap
+    pub lap_started_at: Option<f64>,
+    pub lap_points: Vec<Vec3>,
+    // this trail's part of the trails mesh, rebuilt when the points change
+    ribbon: RibbonBuffers,
+    changed: bool,
+}
+
+impl CarTrail {
+    fn restart_lap(&mut self, lap: usize, seconds: Option<f64>) {
+        self.lap = lap;
+        self.lap_started_at = seconds;
+        self.lap_points.clear();
+    }
+
+    // follows the car's lap count, returns the time and points of a lap it just completed
+    fn count_lap(&mut self, car_laps: usize, seconds: f64) -> Option<(f32, Vec<Vec3>)> {
+        if car_laps < self.lap {
+            self.restart_lap(car_laps, None);
+            return None;
+        }
+        if car_laps == self.lap {
+            return None;
+        }
+        let lap = self.lap_started_at.map(|started_at| {
+            (
+                (seconds - started_at) as f32,
+                std::mem::take(&mut self.lap_points),
+            )
+        });
+        self.restart_lap(car_laps, Some(seconds));
+        lap
+    }
+
+    fn clear_points(&mut self) {
+        if !self.points.is_empty() {
+            self.points.clear();
+            self.changed = true;
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct TrailsMesh;
+
+#[derive(Component)]
+pub struct RacingLineMesh;
+
+fn trail_color(color: TrailColor, car: &Car, speed: f32) -> Color {
+    match color {
+        TrailColor::Speed => {
+            let t = (speed / SPEED_MAX).clamp(0., 1.);
+            Color::rgba(t, 0.3, 1. - t, 0.8)
+        }
+        TrailColor::Input => Color::rgba(car.brake, car.gas, 0.3, 0.8),
+    }
+}
+
+// flat strip along the points, alpha fading towards the oldest end
+fn ribbon(mesh: &mut RibbonBuffers, points: &[(Vec3, Color)], width: f32, fade: bool) {
+    let n = points.len();
+    for (i, pair) in points.windows(2).enumerate() {
+        let (a, ca) = pair[0];
+        let (b, cb) = pair[1];
+        let side = (b - a).cross(Vec3::Y).normalize_or_zero() * width / 2.;
+        let alpha = |j: usize, mut c: Color| {
+            if fade {
+                c.set_a(c.a() * j as f32 / n as f32);
+            }
+            c.as_rgba_f32()
+        };
+        let quad = [
+            (a - side, alpha(i, ca)),
+            (a + side, alpha(i, ca)),
+            (b + side, alpha(i + 1, cb)),
+            (a - side, alpha(i, ca)),
+            (b + side, alpha(i + 1, cb)),
+            (b - side, alpha(i + 1, cb)),
+        ];
+        for (p, c) in quad {
+            mesh.positions.push(p.to_array());
+            mesh.colors.push(c);
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct RibbonBuffers {
+    pub positions: Vec<[f32; 3]>,
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl RibbonBuffers {
+    pub fn into_mesh(self) -> Mesh {
+        let normals: Vec<[f32; 3]> = self.positions.iter().map(|_| [0., 1., 0.]).collect();
+        let uvs: Vec<[f32; 2]> = self.positions.iter().map(|_| [0., 0.]).collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::from(self.positions),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            VertexAttributeValues::from(self.colors),
+        );
+        mesh
+    }
+}
+
+pub fn trails_start_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        alpha_mode: AlphaMode::Blend,
+        cull_mode: None,
+        ..default()
+    });
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(RibbonBuffers::default().into_mesh()),
+            material: material.clone(),
+            ..default()
+        })
+        .insert(Name::new("Trails"))
+        .insert(TrailsMesh)
+        .insert(NoFrustumCulling);
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(RibbonBuffers::default().into_mesh()),
+            material,
+            // above the trails
+            transform: Transform::from_translation(Vec3::Y * 0.01),
+            ..default()
+        })
+        .insert(Name::new("Racing line"))
+        .insert(RacingLineMesh)
+        .insert(NoFrustumCulling);
+    let racing_lines = RacingLines::load(Path::new(RACING_LINE_FILE));
+    for (track_name, line) in racing_lines.lines.iter() {
+        println!(
+            "racing line {:.2}s loaded for {}",
+            line.lap_time, track_name
+        );
+    }
+    commands.insert_resource(racing_lines);
+}
+
+// T cycles trails off, colored by speed, by throttle/brake; Y toggles the racing line
+pub fn trails_key_system(keys: Res<Input<KeyCode>>, mut trails: ResMut<Trails>) {
+    if keys.just_pressed(KeyCode::T) {
+        trails.color = match trails.color {
+            None => Some(TrailColor::Speed),
+            Some(TrailColor::Speed) => Some(TrailColor::Input),
+            Some(TrailColor::Input) => None,
+        };
+    }
+    if keys.just_pressed(KeyCode::Y) {
+        trails.racing_line = !trails.racing_line;
+    }
+}
+
+pub fn trails_record_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<Config>,
+    curriculum: Option<Res<Curriculum>>,
+    trails: Res<Trails>,
+    mut racing_lines: ResMut<RacingLines>,
+    q_new: Query<Entity, (With<Car>, Without<CarTrail>)>,
+    mut q_cars: Query<(&Car, &Transform, &Velocity, &CarProgress, &mut CarTrail)>,
+) {
+    for e in q_new.iter() {
+        commands.entity(e).insert(CarTrail::default());
+    }
+    let seconds = time.seconds_since_startup();
+    for (car, transform, velocity, progress, mut trail) in q_cars.iter_mut() {
+        let p = transform.translation - Vec3::Y * GROUND_OFFSET;
+        let last = trail.last;
+        let car_laps = laps(&config, progress.meters);
+        if last.is_some_and(|last| last.distance(p) > TELEPORT_DISTANCE) {
+            trail.clear_points();
+            trail.last = None;
+            trail.restart_lap(car_laps, None);
+        }
+
+        if let Some((lap_time, points)) = trail.count_lap(car_laps, seconds) {
+            let track_name = active_track_name(&config, curriculum.as_deref());
+            if racing_lines.offer(&track_name, lap_time, &points) {
+                println!("racing line {:.2}s for {}", lap_time, track_name);
+                racing_lines.save(Path::new(RACING_LINE_FILE));
+            }
+        }
+
+        // older points fade out even when the car stands still
+        while let Some((at, ..)) = trail.points.front() {
+            if seconds - at <= trails.seconds {
+                break;
+            }
+            trail.points.pop_front();
+            trail.changed = true;
+        }
+        if trails.color.is_none() {
+            trail.clear_points();
+        }
+
+        let moved = last.is_none_or(|last| last.distance(p) >= POINT_SPACING);
+        if !moved {
+            continue;
+        }
+        trail.last = Some(p);
+        if trail.lap_started_at.is_some() {
+            trail.lap_points.push(p);
+        }
+        if let Some(color) = trails.color {
+            let c = trail_color(color, car, velocity.linvel.length());
+            trail.points.push_back((seconds, p, c));
+            trail.changed = true;
+        }
+    }
+}
+
+pub fn trails_system(
+    config: Res<Config>,
+    curriculum: Option<Res<Curriculum>>,
+    trails: Res<Trails>,
+    mut racing_lines: ResMut<RacingLines>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut q_trails: Query<&mut CarTrail>,
+    mut q_trails_mesh: Query<(&Handle<Mesh>, &mut Visibility), With<TrailsMesh>>,
+    mut q_line_mesh: Query<
+        (&Handle<Mesh>, &mut Visibility),
+        (With<RacingLineMesh>, Without<TrailsMesh>),
+    >,
+) {
+    let (handle, mut visibility) = q_trails_mesh.single_mut();
+    visibility.is_visible = trails.color.is_some();
+    let mut changed = false;
+    for mut trail in q_trails.iter_mut().filter(|trail| trail.changed) {
+        let points: Vec<(Vec3, Color)> = trail.points.iter().map(|(_, p, c)| (*p, *c)).collect();
+        let mut buffers = RibbonBuffers::default();
+        ribbon(&mut buffers, &points, TRAIL_WIDTH, true);
+        trail.ribbon = buffers;
+        trail.changed = false;
+        changed = true;
+    }
+    if changed {
+        let mut buffers = RibbonBuffers::default();
+        for trail in q_trails.iter() {
+            buffers.positions.extend_from_slice(&trail.ribbon.positions);
+            buffers.colors.extend_from_slice(&trail.ribbon.colors);
+        }
+        if let Some(mesh) = meshes.get_mut(handle) {
+            *mesh = buffers.into_mesh();
+        }
+    }
+
+    let (handle, mut visibility) = q_line_mesh.single_mut();
+    visibility.is_visible = trails.racing_line;
+    let track_name = active_track_name(&config, curriculum.as_deref());
+    if racing_lines.shown.as_ref() != Some(&track_name) {
+        let points: Vec<(Vec3, Color)> = racing_lines
+            .lines
+            .get(&track_name)
+            .map(|line| line.points.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|p| (Vec3::from(*p), Color::rgba(1., 0.84, 0., 0.7)))
+            .collect();
+        let mut buffers = RibbonBuffers::default();
+        ribbon(&mut buffers, &points, RACING_LINE_WIDTH, false);
+        if let Some(mesh) = meshes.get_mut(handle) {
+            *mesh = buffers.into_mesh();
+        }
+        racing_lines.shown = Some(track_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(n: usize) -> Vec<Vec3> {
+        (0..n).map(|i| Vec3::X * i as f32).collect()
+    }
+
+    #[test]
+    fn the_standing_start_is_not_a_lap() {
+        let mut trail = CarTrail::default();
+        assert_eq!(trail.count_lap(0, 1.), None);
+        // first crossing of the line starts the first timed lap
+        assert_eq!(trail.count_lap(1, 10.), None);
+        trail.lap_points = line(3);
+        assert_eq!(trail.count_lap(1, 20.), None);
+        assert_eq!(trail.count_lap(2, 70.5), Some((60.5, line(3))));
+        assert!(trail.lap_points.is_empty());
+        assert_eq!(trail.lap_started_at, Some(70.5));
+    }
+
+    #[test]
+    fn driving_back_over_the_line_drops_the_lap() {
+        let mut trail = CarTrail::default();
+        trail.count_lap(1, 10.);
+        trail.lap_points = line(3);
+        assert_eq!(trail.count_lap(0, 15.), None);
+        assert_eq!(trail.lap_started_at, None);
+        assert!(trail.lap_points.is_empty());
+        // crossing again only starts a lap
+        assert_eq!(trail.count_lap(1, 20.), None);
+        assert_eq!(trail.lap_started_at, Some(20.));
+    }
+
+    #[test]
+    fn a_reset_drops_the_lap() {
+        let mut trail = CarTrail::default();
+        trail.count_lap(1, 10.);
+        trail.restart_lap(0, None);
+        assert_eq!(trail.count_lap(1, 40.), None);
+        assert_eq!(trail.count_lap(2, 100.), Some((60., vec![])));
+    }
+
+    #[test]
+    fn only_a_faster_lap_replaces_the_racing_line() {
+        let mut lines = RacingLines::default();
+        assert!(lines.offer("a", 60., &line(2)));
+        assert!(!lines.offer("a", 61., &line(3)));
+        assert!(!lines.offer("a", 60., &line(3)));
+        assert!(lines.offer("a", 59., &line(4)));
+        assert_eq!(lines.lines["a"].lap_time, 59.);
+        assert_eq!(lines.lines["a"].points.len(), 4);
+    }
+
+    #[test]
+    fn every_track_variant_keeps_its_own_line() {
+        let mut lines = RacingLines::default();
+        lines.offer("a", 60., &line(2));
+        // slower than on "a" but the first on "a-mirrored"
+        assert!(lines.offer("a-mirrored", 70., &line(3)));
+        assert_eq!(lines.lines["a"].lap_time, 60.);
+        assert_eq!(lines.lines["a-mirrored"].lap_time, 70.);
+    }
+
+    #[test]
+    fn a_better_line_on_the_shown_track_is_redrawn() {
+        let mut lines = RacingLines {
+            shown: Some("a".to_string()),
+            ..default()
+        };
+        lines.offer("b", 60., &line(2));
+        assert_eq!(lines.shown.as_deref(), Some("a"));
+        lines.offer("a", 60., &line(2));
+        assert_eq!(lines.shown, None);
+    }
+
+    #[test]
+    fn racing_lines_survive_a_save() {
+        let path = std::env::temp_dir().join(format!(
+            "car-sim-{}-{}",
+            std::process::id(),
+            RACING_LINE_FILE
+        ));
+        let mut lines = RacingLines::default();
+        lines.offer("a", 60., &line(2));
+        lines.offer("a-reversed", 65., &line(3));
+        lines.save(&path);
+        let loaded = RacingLines::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.lines.len(), 2);
+        assert_eq!(
+            loaded.lines["a-reversed"].points,
+            lines.lines["a-reversed"].points
+        );
+        assert_eq!(loaded.shown, None);
+    }
+}
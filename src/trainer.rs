@@ -1,28 +1,95 @@
-use crate::{brain::*, car::Car, config::Config, progress::*};
-use bevy::prelude::*;
+use crate::{
+    brain::*,
+    brain_file::save_brain,
+    car::{Car, CarId},
+    config::Config,
+    contact::CarContacts,
+    curriculum::{active_track_name, Curriculum},
+    fitness::{CarFitness, CarStats},
+    noise::CarNoise,
+    rng::SimRng,
+    termination::*,
+    worlds::*,
+};
+use bevy::{ecs::query::WorldQuery, prelude::*};
 use bevy_rapier3d::prelude::{ExternalForce, Velocity};
-use std::{cmp::Ordering, fs};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, path::Path};
 
-const PAUSE: f64 = 3.;
+pub const PAUSE: f64 = 3.;
 const LINVEL_FORCE: f32 = 10000.;
 const ANGVEL_FORCE: f32 = 2.;
 
-pub struct Trainer {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GaConfig {
     pub interval: f64,
+    pub minimal_progress_delta: f32,
+    pub mutation: f32,
+    pub checkpoint_every: i32,
+}
+
+impl Default for GaConfig {
+    fn default() -> Self {
+        Self {
+            interval: 20.,
+            minimal_progress_delta: 1.,
+            mutation: 0.01,
+            checkpoint_every: 1,
+        }
+    }
+}
+
+pub struct Trainer {
+    pub ga: GaConfig,
     pub generation: i32,
     pub record: f32,
     pub last_check_at: f64,
+    pub generation_started_at: f64,
     pub best_brain: Option<CarBrain>,
+    // brains of the running generation by world and car id
+    pub populations: Vec<Vec<CarBrain>>,
+    pub pending: Option<PendingCheck>,
+}
+
+// a check of the main world waiting for the reports of the other worlds
+pub struct PendingCheck {
+    pub started_at: f64,
+    pub reports: Vec<WorldReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CarResult {
+    pub id: usize,
+    pub fitness: f32,
+    // weighted terms of the fitness
+    pub breakdown: Vec<(&'static str, f32)>,
+    pub meters: f32,
+    pub lap_times: Vec<f32>,
+    pub termination: Option<Termination>,
+}
+
+// sent when a new generation starts, results are of the generation that ended
+pub struct GenerationEvent {
+    pub generation: i32,
+    // the track variant the generation ended on
+    pub track: String,
+    pub results: Vec<CarResult>,
+    pub diversity: f32,
+    pub seconds: f64,
 }
 
 impl Default for Trainer {
     fn default() -> Self {
         Self {
-            interval: 20.,
+            ga: GaConfig::default(),
             generation: 0,
             record: 0.,
             last_check_at: 0.,
+            generation_started_at: 0.,
             best_brain: None,
+            populations: vec![],
+            pending: None,
         }
     }
 }
@@ -34,20 +101,31 @@ pub struct TrainerRecordDistanceText;
 #[derive(Component)]
 pub struct TrainerGenerationText;
 
+// what trainer_system reads and resets of a car
+#[derive(WorldQuery)]
+#[world_query(mutable)]
+pub struct TrainerCar<'w> {
+    id: &'w CarId,
+    brain: &'w mut CarBrain,
+    transform: &'w mut Transform,
+    car: &'w mut Car,
+    force: &'w mut ExternalForce,
+    noise: &'w mut CarNoise,
+    fitness: &'w CarFitness,
+    stats: &'w mut CarStats,
+    contacts: &'w mut CarContacts,
+    termination: &'w mut CarTermination,
+}
+
 pub fn trainer_system(
     mut config: ResMut<Config>,
     mut trainer: ResMut<Trainer>,
+    mut rng: ResMut<SimRng>,
+    worlds: Option<Res<TrainingWorlds>>,
+    mut curriculum: Option<ResMut<Curriculum>>,
+    mut e_generation: EventWriter<GenerationEvent>,
     time: Res<Time>,
-    mut cars: Query<
-        (
-            &mut CarProgress,
-            &mut CarBrain,
-            &mut Transform,
-            &mut Car,
-            &mut ExternalForce,
-        ),
-        With<CarProgress>,
-    >,
+    mut cars: Query<TrainerCar>,
     mut dash_set: ParamSet<(
         Query<&mut Text, With<TrainerTimingText>>,
         Query<&mut Text, With<TrainerRecordDistanceText>>,
@@ -61,85 +139,240 @@ pub fn trainer_system(
     if config.reset_pause_until > 0. {
         config.reset_pause_until = 0.;
         config.use_brain = true;
-        for (_, _, _, mut car, mut f) in cars.iter_mut() {
-            car.use_brain = true;
-            *f = ExternalForce::default();
+        for mut c in cars.iter_mut() {
+            c.car.use_brain = true;
+            *c.force = ExternalForce::default();
         }
     }
-    if !config.use_brain {
+    if !config.use_brain || config.race_mode {
         return;
     }
     let seconds_diff = seconds - trainer.last_check_at;
+    let running = cars.iter().filter(|c| c.termination.done.is_none()).count();
+    let all_done = running == 0;
 
-    let mut q_trainer_timing = dash_set.p0();
-    let mut text = q_trainer_timing.single_mut();
-    let round_seconds = ((trainer.interval - seconds_diff) * 10.).round() / 10.;
-    text.sections[1].value = round_seconds.to_string();
+    if let Ok(mut text) = dash_set.p0().get_single_mut() {
+        let round_seconds = ((trainer.ga.interval - seconds_diff) * 10.).round() / 10.;
+        text.sections[1].value = format!("{} running {}", round_seconds, running);
+    }
 
-    if seconds_diff > trainer.interval {
+    let mut command: Option<WorldCommand> = None;
+    let mut reports: Option<Vec<WorldReport>> = None;
+    if (seconds_diff > trainer.ga.interval || all_done) && trainer.pending.is_none() {
         trainer.last_check_at = seconds;
 
-        let best_car = cars
+        let report = WorldReport {
+            world: worlds.as_ref().map_or(0, |w| w.index()),
+            generation: trainer.generation,
+            round: 0,
+            results: cars
+                .iter()
+                .map(|c| CarResult {
+                    id: c.id.0,
+                    fitness: c.fitness.total,
+                    breakdown: c.fitness.breakdown.clone(),
+                    meters: c.stats.meters,
+                    lap_times: c.stats.lap_times.clone(),
+                    termination: c.termination.done,
+                })
+                .collect(),
+            brains: cars.iter().map(|c| c.brain.clone()).collect(),
+            running,
+        };
+
+        match worlds.as_deref() {
+            Some(worker) if worker.is_worker() => command = Some(worker.exchange(report)),
+            // the other worlds check on their own clocks, wait for them over the next frames
+            Some(_) => {
+                trainer.pending = Some(PendingCheck {
+                    started_at: seconds,
+                    reports: vec![report],
+                })
+            }
+            None => reports = Some(vec![report]),
+        }
+    }
+    let (generation, timeout) = (trainer.generation, trainer.ga.interval + PAUSE * 2.);
+    if let (Some(worlds), Some(pending)) = (worlds.as_deref(), trainer.pending.as_mut()) {
+        let complete = worlds.poll(generation, &mut pending.reports);
+        let timed_out = seconds - pending.started_at > timeout;
+        if timed_out && !complete {
+            println!("training worlds did not all report, going on without them");
+        }
+        if complete || timed_out {
+            reports = trainer.pending.take().map(|pending| pending.reports);
+        }
+    }
+
+    if let Some(mut reports) = reports {
+        reports.sort_by_key(|r| r.world);
+        let all_done = reports.iter().all(|r| r.running == 0);
+        let cars_per_world = reports[0].results.len();
+        let mut results: Vec<CarResult> = reports
             .iter()
-            .max_by(|a, b| {
-                if a.0.meters > b.0.meters {
-                    return Ordering::Greater;
-                }
-                Ordering::Less
+            .flat_map(|r| {
+                r.results.iter().map(move |c| CarResult {
+                    id: r.world * cars_per_world + c.id,
+                    ..c.clone()
+                })
             })
-            .unwrap();
-        let (progress, best_brain, _, _, _) = best_car;
-        trainer.best_brain = Some(best_brain.clone());
-        let best_brain = best_brain.clone();
-
-        let minimal_progress_delta = 1.;
-        if progress.meters > (trainer.record + minimal_progress_delta) {
-            println!("distance record {:.1}", progress.meters);
-            trainer.record = progress.meters;
-        } else {
-            trainer.generation += 1;
+            .collect();
+        let brains: Vec<&CarBrain> = reports.iter().flat_map(|r| r.brains.iter()).collect();
+
+        let mut decided = WorldCommand::Continue;
+        if run_ended(&mut trainer, &results, all_done) {
+            let next_track = match curriculum {
+                Some(ref mut curriculum) => curriculum.record(&results, config.meters_total),
+                None => None,
+            };
+            decided = match next_track {
+                Some(track) => WorldCommand::NextTrack { track },
+                None => {
+                    let track_name = active_track_name(&config, curriculum.as_deref());
+                    let track = curriculum.as_mut().map(|c| c.finish(&mut results));
+                    let best_i = results
+                        .iter()
+                        .enumerate()
+                        .max_by(|(_, a), (_, b)| {
+                            a.fitness.partial_cmp(&b.fitness).unwrap_or(Ordering::Equal)
+                        })
+                        .map_or(0, |(i, _)| i);
+                    let brain = brains[best_i].clone();
+                    let populations =
+                        next_populations(&reports, &brain, trainer.ga.mutation, &mut rng.rng);
+                    trainer.best_brain = Some(brain.clone());
+                    trainer.populations = populations.clone();
+                    trainer.generation += 1;
+                    let generation_seconds = seconds - trainer.generation_started_at;
+                    trainer.generation_started_at = seconds;
+                    println!("new generation {:?}", trainer.generation);
+                    e_generation.send(GenerationEvent {
+                        generation: trainer.generation,
+                        track: track_name,
+                        results,
+                        diversity: weight_diversity(&brains),
+                        seconds: generation_seconds,
+                    });
+
+                    match save_brain(Path::new("brain.json"), &brain, config.sensor_layout()) {
+                        Ok(()) => println!("brain.json saved"),
+                        Err(e) => println!("unable to save brain: {}", e),
+                    }
+                    WorldCommand::NextGeneration {
+                        generation: trainer.generation,
+                        brain,
+                        populations,
+                        track,
+                    }
+                }
+            };
+        }
+        if let Some(ref worlds) = worlds {
+            worlds.broadcast(&decided);
+        }
+        command = Some(decided);
+    }
+
+    if let Some(command) = command {
+        let reset = match command {
+            WorldCommand::Continue => None,
+            WorldCommand::NextTrack { track } => Some((None, Some(track))),
+            WorldCommand::NextGeneration {
+                generation,
+                brain,
+                populations,
+                track,
+            } => {
+                trainer.generation = generation;
+                Some((Some((brain, populations)), track))
+            }
+        };
+        if let Some((parent, track)) = reset {
+            if let (Some(track), Some(curriculum)) = (track, curriculum.as_mut()) {
+                curriculum.active = track;
+            }
             trainer.record = 0.;
             config.use_brain = false;
             config.reset_pause_until = time.seconds_since_startup() + 5.;
-            for (_i, (_progress, mut brain, mut t, mut car, mut f)) in cars.iter_mut().enumerate() {
-                let cloned_best: CarBrain = CarBrain::clone_randomised(&best_brain);
-                brain.levels = cloned_best.levels.clone();
-                car.gas = 0.;
-                car.brake = 0.;
-                car.steering = 0.;
-                car.use_brain = false;
-                *t = car.init_transform;
-                *f = ExternalForce::default()
-            }
-            println!("new generation {:?}", trainer.generation);
-
-            let mut brain_dump = best_brain.clone();
-            for level in brain_dump.levels.iter_mut() {
-                level.inputs.fill(0.);
-                level.outputs.fill(0.);
+            let world = worlds.as_ref().map_or(0, |w| w.index());
+            for mut c in cars.iter_mut() {
+                // the same brains drive the next track of the rotation
+                if let Some((ref brain, ref populations)) = parent {
+                    // a world that did not report in time mutates the best brain itself
+                    let next = match populations.get(world).and_then(|p| p.get(c.id.0)) {
+                        Some(next) => next.clone(),
+                        None => {
+                            CarBrain::clone_randomised(brain, trainer.ga.mutation, &mut rng.rng)
+                        }
+                    };
+                    c.brain.levels = next.levels;
+                }
+                c.car.gas = 0.;
+                c.car.brake = 0.;
+                c.car.steering = 0.;
+                c.car.use_brain = false;
+                *c.transform = c.car.init_transform;
+                *c.force = ExternalForce::default();
+                c.noise.clear();
+                *c.stats = CarStats::default();
+                c.contacts.clear();
+                c.termination.clear();
             }
-            let serialized = serde_json::to_string(&brain_dump).unwrap();
-            println!("saving brain.json");
-            fs::write("brain.json", serialized).expect("Unable to write brain.json");
         }
     }
 
-    let mut q_record_distance_text = dash_set.p1();
-    let mut record_text = q_record_distance_text.single_mut();
-    record_text.sections[1].value = ((trainer.record * 10.).round() / 10.).to_string();
+    if let Ok(mut record_text) = dash_set.p1().get_single_mut() {
+        record_text.sections[1].value = ((trainer.record * 10.).round() / 10.).to_string();
+    }
+    if let Ok(mut generation_text) = dash_set.p2().get_single_mut() {
+        generation_text.sections[1].value = trainer.generation.to_string();
+    }
+}
 
-    let mut q_generation_text = dash_set.p2();
-    let mut generation_text = q_generation_text.single_mut();
-    generation_text.sections[1].value = trainer.generation.to_string();
+// the rendered world mutates every world's next generation so a checkpoint holds them all
+fn next_populations<R: Rng>(
+    reports: &[WorldReport],
+    parent: &CarBrain,
+    mutation: f32,
+    rng: &mut R,
+) -> Vec<Vec<CarBrain>> {
+    let n_worlds = reports.iter().map(|r| r.world + 1).max().unwrap_or(0);
+    let mut populations = vec![vec![]; n_worlds];
+    for r in reports.iter() {
+        // by car id, cars without a brain get one they never use
+        let n_cars = r.results.iter().map(|c| c.id + 1).max().unwrap_or(0);
+        populations[r.world] = (0..n_cars)
+            .map(|_| CarBrain::clone_randomised(parent, mutation, rng))
+            .collect();
+    }
+    populations
+}
+
+// a run goes on while the fitness record keeps improving
+fn run_ended(trainer: &mut Trainer, results: &[CarResult], all_done: bool) -> bool {
+    let best_fitness = results.iter().map(|r| r.fitness).fold(f32::MIN, f32::max);
+    let improved = best_fitness > (trainer.record + trainer.ga.minimal_progress_delta);
+    if improved {
+        println!("fitness record {:.1}", best_fitness);
+        trainer.record = best_fitness;
+    }
+    !improved || all_done
 }
 
 pub fn reset_pos_system(
     config: Res<Config>,
     time: Res<Time>,
-    mut q_car: Query<(&mut Transform, &mut Car, &mut ExternalForce, &Velocity)>,
+    mut q_car: Query<(
+        &mut Transform,
+        &mut Car,
+        &mut ExternalForce,
+        &Velocity,
+        Option<&mut CarTermination>,
+        Option<&CarBrain>,
+    )>,
 ) {
     let seconds = time.seconds_since_startup();
-    for (mut t, mut car, mut f, v) in q_car.iter_mut() {
+    for (mut t, mut car, mut f, v, mut termination, brain) in q_car.iter_mut() {
         if t.translation.y > 500. || t.translation.y < 0.
         // || v.linvel.length() > 100.
         // || v.angvel.length() > PI
@@ -151,7 +384,12 @@ pub fn reset_pos_system(
             car.use_brain = false;
             car.reset_pause_until = seconds + PAUSE;
             *t = car.init_transform;
-            *f = ExternalForce::default()
+            *f = ExternalForce::default();
+            if let Some(ref mut termination) = termination {
+                if config.use_brain && !config.race_mode && brain.is_some() {
+                    termination.done = Some(Termination::OutOfBounds);
+                }
+            }
         }
         if car.reset_pause_until > seconds {
             *t = car.init_transform;
@@ -160,7 +398,8 @@ pub fn reset_pos_system(
         } else if car.reset_pause_until > 0. {
             *t = car.init_transform;
             *f = ExternalForce::default();
-            car.use_brain = config.use_brain;
+            car.use_brain =
+                brain.is_some() && config.use_brain && termination.is_none_or(|t| t.done.is_none());
             car.reset_pause_until = 0.;
         }
     }
@@ -0,0 +1,241 @@
+use crate::{
+    brain::*, car::*, config::Config, contact::*, curriculum::*, fitness::*, noise::Noise,
+    progress::*, rng::SimRng, termination::*, track::*, trainer::*,
+};
+use bevy::{
+    asset::AssetPlugin, hierarchy::HierarchyPlugin, prelude::*, scene::ScenePlugin,
+    transform::TransformPlugin,
+};
+use bevy_rapier3d::prelude::*;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+// what one world sends the rendered world at every trainer check
+pub struct WorldReport {
+    pub world: usize,
+    // a report from an earlier generation or check came in too late and is dropped
+    pub generation: i32,
+    pub round: u64,
+    pub results: Vec<CarResult>,
+    pub brains: Vec<CarBrain>,
+    pub running: usize,
+}
+
+#[derive(Clone)]
+pub enum WorldCommand {
+    Continue,
+    NextTrack {
+        track: usize,
+    },
+    NextGeneration {
+        generation: i32,
+        brain: CarBrain,
+        populations: Vec<Vec<CarBrain>>,
+        track: Option<usize>,
+    },
+}
+
+// links the rendered world with the headless ones, absent when training in one world
+pub enum TrainingWorlds {
+    Main {
+        reports: Mutex<Receiver<WorldReport>>,
+        workers: Mutex<Vec<Sender<(u64, WorldCommand)>>>,
+        // checks decided so far
+        round: AtomicU64,
+    },
+    Worker {
+        world: usize,
+        reports: Mutex<Sender<WorldReport>>,
+        commands: Mutex<Receiver<(u64, WorldCommand)>>,
+        round: AtomicU64,
+    },
+}
+
+impl TrainingWorlds {
+    pub fn index(&self) -> usize {
+        match self {
+            TrainingWorlds::Main { .. } => 0,
+            TrainingWorlds::Worker { world, .. } => *world,
+        }
+    }
+
+    pub fn is_worker(&self) -> bool {
+        matches!(self, TrainingWorlds::Worker { .. })
+    }
+
+    // worker side, blocks until the rendered world decided
+    pub fn exchange(&self, mut report: WorldReport) -> WorldCommand {
+        if let TrainingWorlds::Worker {
+            reports,
+            commands,
+            round,
+            ..
+        } = self
+        {
+            report.round = round.load(Ordering::Relaxed);
+            if reports.lock().unwrap().send(report).is_ok() {
+                // a late report gets the command of the check it missed
+                if let Ok((decided, command)) = commands.lock().unwrap().recv() {
+                    round.store(decided + 1, Ordering::Relaxed);
+                    return command;
+                }
+            }
+        }
+        WorldCommand::Continue
+    }
+
+    // main side, takes what arrived without waiting, true once every worker still alive reported
+    pub fn poll(&self, generation: i32, gathered: &mut Vec<WorldReport>) -> bool {
+        if let TrainingWorlds::Main {
+            reports,
+            workers,
+            round,
+        } = self
+        {
+            let round = round.load(Ordering::Relaxed);
+            for report in reports.lock().unwrap().try_iter() {
+                if report.generation != generation || report.round != round {
+                    println!("dropping a late report of world {}", report.world);
+                    continue;
+                }
+                gathered.retain(|r| r.world != report.world);
+                gathered.push(report);
+            }
+            // the rendered world's own report is in there too
+            return gathered.len() > workers.lock().unwrap().len();
+        }
+        true
+    }
+
+    pub fn broadcast(&self, command: &WorldCommand) {
+        if let TrainingWorlds::Main { workers, round, .. } = self {
+            let decided = round.fetch_add(1, Ordering::Relaxed);
+            workers
+                .lock()
+                .unwrap()
+                .retain(|worker| worker.send((decided, command.clone())).is_ok());
+        }
+    }
+}
+
+// the rendered world is world 0, the others run headless in their own threads
+pub fn spawn_training_worlds(
+    count: usize,
+    config: &Config,
+    trainer: &Trainer,
+    fitness: &FitnessWeights,
+    rules: &TerminationRules,
+    noise: &Noise,
+    start_brains: &StartBrains,
+    curriculum: Option<&Curriculum>,
+) -> Option<TrainingWorlds> {
+    if count < 2 {
+        return None;
+    }
+    let (report_tx, report_rx) = channel::<WorldReport>();
+    let mut workers: Vec<Sender<(u64, WorldCommand)>> = vec![];
+    for world in 1..count {
+        let (command_tx, command_rx) = channel::<(u64, WorldCommand)>();
+        workers.push(command_tx);
+        let link = TrainingWorlds::Worker {
+            world,
+            reports: Mutex::new(report_tx.clone()),
+            commands: Mutex::new(command_rx),
+            round: AtomicU64::new(0),
+        };
+        let trainer = Trainer {
+            ga: trainer.ga.clone(),
+            generation: trainer.generation,
+            ..default()
+        };
+        // the sensors have to match the brains the main world trades
+        let world_config = Config {
+            car_sensors: config.car_sensors,
+            seed: config.seed + world as u64,
+            ..default()
+        };
+        let fitness = Fitness::new(fitness.clone());
+        let rules = rules.clone();
+        let noise = noise.clone();
+        let start_brains = start_brains.of_world(world);
+        let curriculum = curriculum.cloned();
+        thread::Builder::new()
+            .name(format!("world {}", world))
+            .spawn(move || {
+                headless_world(
+                    link,
+                    world_config,
+                    trainer,
+                    fitness,
+                    rules,
+                    noise,
+                    start_brains,
+                    curriculum,
+                )
+                .run()
+            })
+            .expect("unable to spawn training world");
+    }
+    println!("training in {} worlds", count);
+    Some(TrainingWorlds::Main {
+        reports: Mutex::new(report_rx),
+        workers: Mutex::new(workers),
+        round: AtomicU64::new(0),
+    })
+}
+
+// own track copy and rapier context, no window, no dash
+fn headless_world(
+    link: TrainingWorlds,
+    config: Config,
+    trainer: Trainer,
+    fitness: Fitness,
+    rules: TerminationRules,
+    noise: Noise,
+    start_brains: StartBrains,
+    curriculum: Option<Curriculum>,
+) -> App {
+    let mut app = App::new();
+    if let Some(curriculum) = curriculum {
+        app.insert_resource(curriculum)
+            .add_system(curriculum_track_system.after(trainer_system));
+    }
+    app.insert_resource(link)
+        .insert_resource(SimRng::new(config.seed))
+        .insert_resource(config)
+        .insert_resource(noise)
+        .insert_resource(trainer)
+        .insert_resource(fitness)
+        .insert_resource(start_brains)
+        .insert_resource(rules)
+        .init_resource::<BrainActivity>()
+        .add_event::<GenerationEvent>()
+        .add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(ScenePlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_startup_system(track_start_system)
+        .add_startup_system(track_polyline_start_system)
+        .add_startup_system(car_start_system)
+        .add_system(car_brain_system)
+        .add_system(trainer_system)
+        .add_system(reset_pos_system)
+        .add_system(progress_system)
+        .add_system(reset_force_system)
+        .add_system(car_contacts_system)
+        .add_system(car_stats_system)
+        .add_system(car_fitness_system)
+        .add_system(termination_system)
+        .add_system_to_stage(CoreStage::PostUpdate, car_contact_forces_system);
+    app
+}